@@ -0,0 +1,145 @@
+//! Zip and tar archives as build input, e.g. figure bundles attached to issues or produced by other pipelines.
+//! The archive is unpacked to a temporary folder, which is searched like an input folder and removed again after the build
+
+use flate2::read::GzDecoder;
+use snafu::prelude::*;
+use std::fs::{self, create_dir_all, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::discover::walk_drawio_files;
+use crate::AppError;
+
+/// Folder with the unpacked content of an archive. Removed when dropped
+pub struct UnpackedArchive {
+    dir: PathBuf,
+}
+
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// True if `input` is a file that we can unpack
+pub fn is_archive(input: &str) -> bool {
+    let path = Path::new(input);
+    path.is_file() && archive_kind(path).is_some()
+}
+
+impl UnpackedArchive {
+    /// Unpack the zip, tar or tar.gz archive at `path` to a new temporary folder
+    pub fn unpack(path: &Path) -> Result<Self, AppError> {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-input-{}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).whatever_context::<String, AppError>(format!("failed to clean {:?}", &dir))?;
+        }
+        create_dir_all(&dir).whatever_context::<String, AppError>(format!("failed to create {:?}", &dir))?;
+        //constructed before unpacking, so that a partially unpacked archive is removed as well
+        let unpacked = UnpackedArchive { dir };
+        let file = File::open(path).whatever_context::<String, AppError>(format!("failed to open archive {:?}", path))?;
+        let result = match archive_kind(path) {
+            Some(ArchiveKind::Zip) => unpack_zip(file, &unpacked.dir),
+            Some(ArchiveKind::Tar) => unpack_tar(file, &unpacked.dir),
+            Some(ArchiveKind::TarGz) => unpack_tar(GzDecoder::new(file), &unpacked.dir),
+            None => whatever!("{:?} is not a zip or tar archive", path),
+        };
+        result.whatever_context::<String, AppError>(format!("failed to unpack archive {:?}", path))?;
+        Ok(unpacked)
+    }
+
+    /// All .drawio files in the archive. Like for input folders, sub folders are only searched if `recursive` is set
+    /// and files excluded by ignore files are skipped if `respect_ignore_files` is set
+    pub fn drawio_files(&self, recursive: bool, respect_ignore_files: bool) -> Result<Vec<PathBuf>, AppError> {
+        walk_drawio_files(&self.dir, recursive, respect_ignore_files)
+    }
+}
+
+impl Drop for UnpackedArchive {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn unpack_tar(reader: impl Read, dir: &Path) -> std::io::Result<()> {
+    //keeps the modification times of the entries, so that incremental builds work across runs
+    tar::Archive::new(reader).unpack(dir)
+}
+
+fn unpack_zip(file: File, dir: &Path) -> std::io::Result<()> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        //entries with absolute paths or ".." could write outside of `dir`
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        let target = dir.join(name);
+        if entry.is_dir() {
+            create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut out = File::create(&target)?;
+        std::io::copy(&mut entry, &mut out)?;
+        //zip stores local time without a time zone. A skewed result is handled like any other future timestamp
+        let modified = entry
+            .last_modified()
+            .and_then(|v| time::OffsetDateTime::try_from(v).ok())
+            .map(SystemTime::from);
+        if let Some(modified) = modified {
+            out.set_modified(modified)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_unpack_zip() {
+        let path = std::env::temp_dir().join(format!("drawio-builder-test-{}.zip", std::process::id()));
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("figures/a.drawio", options).unwrap();
+        writer.write_all(b"<mxfile/>").unwrap();
+        writer.start_file("b.drawio", options).unwrap();
+        writer.write_all(b"<mxfile/>").unwrap();
+        writer.start_file("notes.txt", options).unwrap();
+        writer.start_file("../escape.drawio", options).unwrap();
+        writer.finish().unwrap();
+
+        let unpacked = UnpackedArchive::unpack(&path).unwrap();
+        let names: Vec<_> = unpacked
+            .drawio_files(true, false)
+            .unwrap()
+            .iter()
+            .map(|v| v.strip_prefix(&unpacked.dir).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(names, [PathBuf::from("b.drawio"), PathBuf::from("figures/a.drawio")]);
+        assert_eq!(unpacked.drawio_files(false, false).unwrap(), [unpacked.dir.join("b.drawio")]);
+        let dir = unpacked.dir.clone();
+        drop(unpacked);
+        assert!(!dir.exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+//! `audit` subcommand: compare the figures used by LaTeX sources with the exported figures, to find
+//! figures that are exported but never used and used figures that are neither exported nor exist
+
+use clap::Args;
+use ignore::WalkBuilder;
+use snafu::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{input_files, layer_steps, load_config, load_translations, output_path, plan_build, AppError, DEFAULT_BUILD_ARGS};
+
+#[derive(Args)]
+pub struct AuditArgs {
+    ///Path to a .tex file or a folder, which is searched recursively for .tex files
+    #[arg(long)]
+    tex: String,
+
+    ///Path to folder with input files
+    #[arg(short, long, default_value = "./")]
+    input: String,
+
+    ///Path to folder with the exported figures
+    #[arg(short, long, default_value = "./out")]
+    output: String,
+
+    ///Path to optional config file
+    #[arg(long)]
+    config: Option<String>,
+
+    ///Drawio build args, as passed to the build. Required to determine the exported page
+    #[arg(long, default_value = DEFAULT_BUILD_ARGS)]
+    build_args: String,
+
+    ///Set if the build exports all pages
+    #[arg(long, default_value = "false")]
+    all_pages: bool,
+
+    ///Translation folder, if the build exports localized figures
+    #[arg(long)]
+    locales: Option<String>,
+
+    ///Set if the build searches sub folders of the input folder
+    #[arg(long, default_value = "false")]
+    recursive: bool,
+
+    ///Set if the build skips input files excluded by .gitignore or .ignore files
+    #[arg(long, default_value = "false")]
+    respect_ignore_files: bool,
+}
+
+/// Extensions LaTeX tries for `\includegraphics` without an extension
+const GRAPHICS_EXTENSIONS: &[&str] = &["pdf", "png", "jpg", "jpeg", "eps", "svg"];
+
+/// A `\includegraphics` in a .tex file
+struct Reference {
+    tex_file: PathBuf,
+    line: usize,
+    path: String,
+}
+
+/// Content of the `{...}` group starting at the beginning of `text`
+fn group(text: &str) -> Option<&str> {
+    let text = text.trim_start().strip_prefix('{')?;
+    let mut depth = 0;
+    for (idx, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(&text[..idx]),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Line number and path of all `\includegraphics` in `tex`, as well as the folders of `\graphicspath`
+fn parse_tex(tex: &str) -> (Vec<(usize, String)>, Vec<String>) {
+    let mut references = Vec::new();
+    let mut graphics_paths = Vec::new();
+    for (idx, line) in tex.lines().enumerate() {
+        //drop comments, but not escaped percent signs
+        //a percent sign is escaped by an odd number of backslashes, `\\%` is a line break followed by a comment
+        let escaped = |pos: usize| line[..pos].chars().rev().take_while(|v| *v == '\\').count() % 2 == 1;
+        let line = match line.match_indices('%').find(|(pos, _)| !escaped(*pos)) {
+            Some((pos, _)) => &line[..pos],
+            None => line,
+        };
+        for (pos, _) in line.match_indices("\\includegraphics") {
+            let mut rest = line[pos + "\\includegraphics".len()..].trim_start();
+            rest = rest.strip_prefix('*').unwrap_or(rest).trim_start();
+            if let Some(options) = rest.strip_prefix('[') {
+                let Some(end) = options.find(']') else {
+                    continue;
+                };
+                rest = &options[end + 1..];
+            }
+            if let Some(path) = group(rest) {
+                references.push((idx + 1, path.trim().to_string()));
+            }
+        }
+        if let Some(pos) = line.find("\\graphicspath") {
+            let mut rest = group(&line[pos + "\\graphicspath".len()..]).unwrap_or_default();
+            while let Some(path) = group(rest) {
+                graphics_paths.push(path.to_string());
+                rest = &rest.trim_start()[path.len() + 2..];
+            }
+        }
+    }
+    (references, graphics_paths)
+}
+
+/// Absolute form of `path` without "." and "..", so that different spellings of the same file compare equal.
+/// Existing files are canonicalized, which also resolves symlinks
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    let path = std::env::current_dir().unwrap_or_default().join(path);
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Files LaTeX may load for `path` from `dirs`, i.e. `path` relative to each folder, with or without an added
+/// graphics extension. Localized outputs are named e.g. "figure-0.de.png", so other dots do not count as extension
+fn candidates(path: &str, dirs: &[PathBuf]) -> Vec<PathBuf> {
+    dirs.iter()
+        .flat_map(|dir| {
+            let candidate = dir.join(path);
+            let with_extensions = GRAPHICS_EXTENSIONS.iter().map(move |ext| PathBuf::from(format!("{}.{}", dir.join(path).display(), ext)));
+            std::iter::once(candidate).chain(with_extensions)
+        })
+        .map(|v| normalize(&v))
+        .collect()
+}
+
+pub fn run(args: &AuditArgs) -> Result<(), AppError> {
+    let config = load_config(args.config.as_deref())?;
+    let drawio_flags: Vec<String> = args.build_args.split(' ').map(|v| v.to_string()).collect();
+    let translations = load_translations(args.locales.as_deref())?;
+    let files = input_files(&args.input, args.recursive, args.respect_ignore_files)?;
+    let planned = plan_build(files, &config, &drawio_flags, args.all_pages, &translations, &mut |v| {
+        eprintln!("Warning: {}", v)
+    })?;
+    let outputs: Vec<PathBuf> = planned
+        .iter()
+        .flat_map(|(file, build_config)| {
+            (0..layer_steps(&build_config.layer_config).len()).map(|idx| output_path(file, build_config, &args.output, idx))
+        })
+        .collect();
+
+    let tex_files = tex_files(Path::new(&args.tex))?;
+    let mut references = Vec::new();
+    //the \graphicspath is usually set in the main file and applies to all included files. Relative folders are
+    //resolved against the folder LaTeX runs in, which is usually the one of the main file
+    let mut graphics_dirs = Vec::new();
+    for tex_file in &tex_files {
+        let tex = fs::read_to_string(tex_file).whatever_context::<String, AppError>(format!("failed to read {:?}", tex_file))?;
+        let (file_references, graphics_paths) = parse_tex(&tex);
+        let tex_dir = tex_file.parent().unwrap_or(Path::new("")).to_path_buf();
+        graphics_dirs.extend(graphics_paths.iter().flat_map(|v| [PathBuf::from(v), tex_dir.join(v)]));
+        references.extend(file_references.into_iter().map(|(line, path)| Reference {
+            tex_file: tex_file.clone(),
+            line,
+            path,
+        }));
+    }
+
+    let produced: BTreeSet<PathBuf> = outputs.iter().map(|v| normalize(v)).collect();
+    let mut used = BTreeSet::new();
+    let mut missing = Vec::new();
+    for reference in &references {
+        let tex_dir = reference.tex_file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let dirs: Vec<PathBuf> = [PathBuf::from("."), tex_dir].into_iter().chain(graphics_dirs.iter().cloned()).collect();
+        let candidates = candidates(&reference.path, &dirs);
+        let exported: Vec<&PathBuf> = candidates.iter().filter(|v| produced.contains(*v)).collect();
+        if exported.is_empty() && !candidates.iter().any(|v| v.is_file()) {
+            missing.push(reference);
+        }
+        used.extend(exported.into_iter().cloned());
+    }
+    let unused: Vec<&PathBuf> = outputs.iter().filter(|v| !used.contains(&normalize(v))).collect();
+
+    if !unused.is_empty() {
+        println!("Exported figures that are not used by any .tex file:");
+        for output in &unused {
+            println!("  {}", output.display());
+        }
+    }
+    if !missing.is_empty() {
+        println!("Used figures that are neither exported nor exist:");
+        for reference in &missing {
+            println!("  {}:{}: {}", reference.tex_file.display(), reference.line, reference.path);
+        }
+    }
+    if !unused.is_empty() || !missing.is_empty() {
+        whatever!("Audit found {} unused and {} missing figure(s)", unused.len(), missing.len());
+    }
+    println!("All {} exported figures are used and all {} used figures exist", outputs.len(), references.len());
+    Ok(())
+}
+
+/// `path` if it is a file, or all .tex files in the folder `path` and its sub folders
+fn tex_files(path: &Path) -> Result<Vec<PathBuf>, AppError> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    //symlinked folders are followed, the walker detects symlink loops
+    let walker = WalkBuilder::new(path).standard_filters(false).follow_links(true).build();
+    let mut files = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            //the target of the loop is searched anyway
+            Err(err) if is_loop(&err) => continue,
+            Err(err) => return Err(err).whatever_context::<String, AppError>(format!("error listing files in folder {:?}", path)),
+        };
+        if entry.path().is_file() && entry.path().extension().is_some_and(|v| v == "tex") {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn is_loop(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. } | ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_tex() {
+        let tex = r"\graphicspath{{figures/}{out/}}
+\includegraphics[width=\linewidth]{out/arch-0}
+\includegraphics*{ logo.pdf }\includegraphics{out/arch-1.de.png}
+% \includegraphics{out/commented-0}
+50\% of \includegraphics{out/arch-2.png} % \includegraphics{out/also-commented}
+\includegraphics{out/arch-3}\\% \includegraphics{out/after-line-break}
+\includegraphics{out/arch-4}\\\% \includegraphics{out/after-escaped-percent}";
+        let (references, graphics_paths) = parse_tex(tex);
+        let references: Vec<_> = references.iter().map(|(line, path)| (*line, path.as_str())).collect();
+        assert_eq!(
+            references,
+            [
+                (2, "out/arch-0"),
+                (3, "logo.pdf"),
+                (3, "out/arch-1.de.png"),
+                (5, "out/arch-2.png"),
+                (6, "out/arch-3"),
+                (7, "out/arch-4"),
+                (7, "out/after-escaped-percent")
+            ]
+        );
+        assert_eq!(graphics_paths, ["figures/", "out/"]);
+    }
+
+    #[test]
+    fn test_candidates() {
+        let dirs = [PathBuf::from("paper"), PathBuf::from("paper/../out")];
+        let candidates = candidates("arch-1.de", &dirs);
+        assert!(candidates.contains(&normalize(Path::new("out/arch-1.de.png"))));
+        assert!(candidates.contains(&normalize(Path::new("paper/arch-1.de"))));
+        //a figure of the same name in another folder is not loaded by LaTeX
+        assert!(!candidates.contains(&normalize(Path::new("old/arch-1.de.png"))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tex_files_symlink_loop() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-audit-{}", std::process::id()));
+        fs::create_dir_all(dir.join("chapters")).unwrap();
+        fs::write(dir.join("main.tex"), "").unwrap();
+        fs::write(dir.join("chapters/intro.tex"), "").unwrap();
+        std::os::unix::fs::symlink("..", dir.join("chapters/up")).unwrap();
+        let files = tex_files(&dir).unwrap();
+        assert_eq!(files, [dir.join("chapters/intro.tex"), dir.join("main.tex")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
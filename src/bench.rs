@@ -0,0 +1,295 @@
+//! `bench` subcommand: export a sample of figures at several concurrency levels and recommend the number of
+//! parallel jobs for this machine. The recommendation is stored in the user's config folder and used by
+//! later builds that do not pass `--jobs`
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+use std::fs::{self, create_dir_all, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{find_drawio_files, AppError, DEFAULT_BUILD_ARGS};
+
+#[derive(Args)]
+pub struct BenchArgs {
+    ///Path to folder with input files
+    #[arg(short, long, default_value = "./")]
+    input: String,
+
+    ///Path to the drawio binary
+    #[arg(long, default_value = "drawio")]
+    drawio: String,
+
+    ///Drawio build args, as passed to the build
+    #[arg(long, default_value = DEFAULT_BUILD_ARGS)]
+    build_args: String,
+
+    ///Number of figures to export at each concurrency level
+    #[arg(long, default_value = "4")]
+    sample: usize,
+
+    ///Comma separated concurrency levels to measure. Defaults to powers of two up to the number of CPUs
+    #[arg(long, value_delimiter = ',')]
+    jobs: Vec<usize>,
+
+    ///Flag passed verbatim to electron/chromium, as passed to the build, e.g. "--no-sandbox". Repeat for multiple flags
+    #[arg(long, allow_hyphen_values = true)]
+    electron_args: Vec<String>,
+
+    ///Only print the recommendation, do not store it for future builds
+    #[arg(long, default_value = "false")]
+    no_save: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Recommendation {
+    jobs: usize,
+}
+
+/// Measurements of one concurrency level
+struct Level {
+    jobs: usize,
+    exports_per_sec: f64,
+    ///Peak memory of all drawio processes in bytes, if we can measure it on this platform
+    peak_memory: Option<u64>,
+}
+
+/// Where the recommendation is stored, i.e. the config folder of the user
+fn recommendation_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|v| PathBuf::from(v).join(".config")))?;
+    Some(config_dir.join("drawio-builder").join("bench.json"))
+}
+
+/// Number of jobs recommended by an earlier `bench` run on this machine, together with the file it is stored in
+pub fn recommended_jobs() -> Option<(usize, PathBuf)> {
+    let path = recommendation_path()?;
+    let file = File::open(&path).ok()?;
+    let recommendation: Recommendation = serde_json::from_reader(BufReader::new(file)).ok()?;
+    Some(recommendation.jobs).filter(|v| *v > 0).map(|v| (v, path))
+}
+
+fn default_levels() -> Vec<usize> {
+    let cpus = std::thread::available_parallelism().map(|v| v.get()).unwrap_or(1);
+    let mut levels: Vec<usize> = std::iter::successors(Some(1), |v| Some(v * 2)).take_while(|v| *v < cpus).collect();
+    levels.push(cpus);
+    levels
+}
+
+/// Resident memory of all descendants of this process in bytes. Electron starts several helper
+/// processes per drawio instance, so we cannot only look at our direct children
+#[cfg(target_os = "linux")]
+fn descendants_memory() -> Option<u64> {
+    let mut parents = std::collections::HashMap::new();
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        //the command name in parentheses may contain spaces, the fields after it do not
+        let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        let ppid = stat.rsplit_once(')').and_then(|(_, v)| v.split_whitespace().nth(1)?.parse::<u32>().ok());
+        if let Some(ppid) = ppid {
+            parents.insert(pid, ppid);
+        }
+    }
+    let own_pid = std::process::id();
+    let is_descendant = |mut pid: u32| {
+        while let Some(ppid) = parents.get(&pid) {
+            if *ppid == own_pid {
+                return true;
+            }
+            pid = *ppid;
+        }
+        false
+    };
+    let mut total = 0;
+    for pid in parents.keys().filter(|v| is_descendant(**v)) {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid)).unwrap_or_default();
+        let rss_kib = status
+            .lines()
+            .find_map(|v| v.strip_prefix("VmRSS:"))
+            .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok());
+        total += rss_kib.unwrap_or(0) * 1024;
+    }
+    Some(total)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn descendants_memory() -> Option<u64> {
+    None
+}
+
+/// Export `files` with `jobs` parallel drawio processes
+fn measure(args: &BenchArgs, files: &[PathBuf], jobs: usize, out_dir: &Path) -> Result<Level, AppError> {
+    let flags: Vec<&str> = args.build_args.split(' ').filter(|v| !v.is_empty()).collect();
+    //every job gets at least two exports, so that all of them are busy for most of the measurement
+    let tasks: Vec<&PathBuf> = files.iter().cycle().take(files.len().max(2 * jobs)).collect();
+    let next_task = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    let peak_memory = std::sync::Mutex::new(descendants_memory());
+    let started = Instant::now();
+    let result = std::thread::scope(|s| {
+        s.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                if let Some(memory) = descendants_memory() {
+                    let mut peak = peak_memory.lock().unwrap();
+                    *peak = Some(peak.unwrap_or(0).max(memory));
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                //AppError is not Send, so the threads report plain messages
+                s.spawn(|| -> Result<(), String> {
+                    loop {
+                        let idx = next_task.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = tasks.get(idx) else {
+                            return Ok(());
+                        };
+                        let output_path = out_dir.join(format!("{}.png", idx));
+                        let status = Command::new(&args.drawio)
+                            .args(&flags)
+                            .arg("-o")
+                            .arg(&output_path)
+                            .arg(file)
+                            .args(&args.electron_args)
+                            .stdout(Stdio::null())
+                            .stderr(Stdio::null())
+                            .status()
+                            .map_err(|e| format!("failed to run {} : {:?}", &args.drawio, e))?;
+                        if !status.success() || !output_path.exists() {
+                            return Err(format!("failed to export {:?} with {} parallel jobs", file, jobs));
+                        }
+                    }
+                })
+            })
+            .collect();
+        let result = workers.into_iter().try_for_each(|v| v.join().unwrap());
+        done.store(true, Ordering::Relaxed);
+        result
+    });
+    if let Err(message) = result {
+        whatever!("{}", message);
+    }
+    Ok(Level {
+        jobs,
+        exports_per_sec: tasks.len() as f64 / started.elapsed().as_secs_f64(),
+        peak_memory: peak_memory.into_inner().unwrap(),
+    })
+}
+
+/// Smallest number of jobs within 5% of the best throughput, as more jobs only cost memory from there on.
+/// Levels whose peak memory exceeds `memory_budget` are not considered
+fn recommend(levels: &[Level], memory_budget: Option<u64>) -> Option<usize> {
+    let fits = |level: &&Level| match (level.peak_memory, memory_budget) {
+        (Some(peak), Some(budget)) => peak <= budget,
+        _ => true,
+    };
+    let best = levels.iter().filter(fits).map(|v| v.exports_per_sec).fold(0.0, f64::max);
+    levels
+        .iter()
+        .filter(fits)
+        .filter(|v| v.exports_per_sec >= 0.95 * best)
+        .map(|v| v.jobs)
+        .min()
+}
+
+/// Memory that may be used by the build without swapping, i.e. 80% of the currently available memory
+#[cfg(target_os = "linux")]
+fn memory_budget() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let available_kib = meminfo
+        .lines()
+        .find_map(|v| v.strip_prefix("MemAvailable:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(available_kib * 1024 / 10 * 8)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn memory_budget() -> Option<u64> {
+    None
+}
+
+pub fn run(args: &BenchArgs) -> Result<(), AppError> {
+    let files: Vec<PathBuf> = find_drawio_files(&args.input)?.into_iter().take(args.sample.max(1)).collect();
+    if files.is_empty() {
+        whatever!("no .drawio files found in {}", &args.input);
+    }
+    let levels = match args.jobs.is_empty() {
+        true => default_levels(),
+        false => args.jobs.iter().copied().filter(|v| *v > 0).collect(),
+    };
+    let memory_budget = memory_budget();
+    let out_dir = std::env::temp_dir().join(format!("drawio-builder-bench-{}", std::process::id()));
+    create_dir_all(&out_dir).whatever_context::<String, AppError>(format!("failed to create {:?}", &out_dir))?;
+
+    println!("{:>5} {:>12} {:>12}", "jobs", "exports/s", "peak memory");
+    let mut results = Vec::new();
+    for jobs in levels {
+        let level = measure(args, &files, jobs, &out_dir);
+        let level = match level {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&out_dir);
+                return Err(e);
+            }
+        };
+        let memory = match level.peak_memory {
+            Some(v) => format!("{:.0} MiB", v as f64 / (1024.0 * 1024.0)),
+            None => "n/a".to_string(),
+        };
+        println!("{:>5} {:>12.2} {:>12}", level.jobs, level.exports_per_sec, memory);
+        results.push(level);
+    }
+    let _ = fs::remove_dir_all(&out_dir);
+
+    let Some(jobs) = recommend(&results, memory_budget) else {
+        whatever!("all concurrency levels exceeded the available memory, try fewer jobs");
+    };
+    println!("Recommended: --jobs {}", jobs);
+    if args.no_save {
+        return Ok(());
+    }
+    let Some(path) = recommendation_path() else {
+        whatever!("failed to determine the config folder to store the recommendation in");
+    };
+    create_dir_all(path.parent().unwrap())
+        .whatever_context::<String, AppError>(format!("failed to create {:?}", path.parent().unwrap()))?;
+    let file = File::create(&path).whatever_context::<String, AppError>(format!("failed to create {:?}", &path))?;
+    serde_json::to_writer_pretty(file, &Recommendation { jobs })
+        .whatever_context::<String, AppError>(format!("failed to write {:?}", &path))?;
+    println!("Stored in {:?}, builds without --jobs use it from now on", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recommend() {
+        let level = |jobs, exports_per_sec, peak_memory| Level {
+            jobs,
+            exports_per_sec,
+            peak_memory: Some(peak_memory),
+        };
+        let levels = [level(1, 1.0, 100), level(2, 1.9, 200), level(4, 3.0, 400), level(8, 3.1, 800)];
+        //8 jobs are only marginally faster than 4
+        assert_eq!(recommend(&levels, None), Some(4));
+        assert_eq!(recommend(&levels, Some(300)), Some(2));
+        assert_eq!(recommend(&levels, Some(50)), None);
+    }
+}
@@ -0,0 +1,177 @@
+//! Size budgets for outputs. Journals and arXiv limit the size of figures, so outputs larger than `max_output_kb`
+//! are reported and, depending on `--oversize`, re-exported at a lower scale or re-encoded with maximum compression
+
+use clap::ValueEnum;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::ImageFormat;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OversizeAction {
+    ///Only report outputs that exceed their budget
+    Warn,
+    ///Re-export outputs that exceed their budget at a lower scale
+    Rescale,
+    ///Re-encode png outputs that exceed their budget with maximum compression
+    Optimize,
+}
+
+/// Smallest scale we re-export at. Below, figures tend to become unreadable
+const MIN_SCALE: f64 = 0.5;
+
+/// An output that is larger than its budget
+pub struct Offender {
+    pub output_path: PathBuf,
+    pub size_kb: u64,
+    pub max_kb: u64,
+}
+
+/// Outputs that exceed their budget, collected during the build to report them at the end
+#[derive(Default)]
+pub struct Offenders(Mutex<Vec<Offender>>);
+
+impl Offenders {
+    pub fn record(&self, output_path: &Path, size_kb: u64, max_kb: u64) {
+        self.0.lock().unwrap().push(Offender {
+            output_path: output_path.to_path_buf(),
+            size_kb,
+            max_kb,
+        });
+    }
+
+    /// All offenders, sorted by output path
+    pub fn finish(self) -> Vec<Offender> {
+        let mut offenders = self.0.into_inner().unwrap();
+        offenders.sort_by(|a, b| a.output_path.cmp(&b.output_path));
+        offenders
+    }
+}
+
+/// Size of `path` in KB, rounded up. Zero if it does not exist
+pub fn size_kb(path: &Path) -> u64 {
+    fs::metadata(path).map(|v| v.len().div_ceil(1024)).unwrap_or_default()
+}
+
+/// Drawio flags with a scale that should shrink an output of `size_kb` to `max_kb`, together with that scale.
+/// The size of raster images grows with the square of the scale. None if the scale would drop below `MIN_SCALE`
+pub fn reduced_scale(flags: &[String], size_kb: u64, max_kb: u64) -> Option<(f64, Vec<String>)> {
+    let idx = flags.iter().position(|v| v == "-s" || v == "--scale");
+    let scale: f64 = match idx {
+        Some(idx) => flags.get(idx + 1)?.parse().ok()?,
+        None => 1.0,
+    };
+    //aim slightly below the budget, as the size does not exactly follow the scale
+    let reduced = (scale * (max_kb as f64 / size_kb as f64).sqrt() * 0.9 * 100.0).floor() / 100.0;
+    if reduced < MIN_SCALE {
+        return None;
+    }
+    let mut flags = flags.to_vec();
+    match idx {
+        Some(idx) => flags[idx + 1] = reduced.to_string(),
+        None => flags.extend(["-s".to_string(), reduced.to_string()]),
+    }
+    Some((reduced, flags))
+}
+
+/// Chunks the re-encoding has to keep. Text chunks hold e.g. the diagram drawio embeds with `--embed-diagram`,
+/// without which the figure can no longer be edited, pHYs holds the resolution
+const KEPT_CHUNKS: [&[u8]; 4] = [b"tEXt", b"zTXt", b"iTXt", b"pHYs"];
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Raw chunks of `png`, each with length, type, data and CRC. None if `png` is malformed
+fn chunks(png: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut rest = png.strip_prefix(PNG_SIGNATURE)?;
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let (chunk, remaining) = rest.split_at_checked(length.checked_add(12)?)?;
+        chunks.push(chunk);
+        rest = remaining;
+    }
+    Some(chunks)
+}
+
+fn chunk_type(chunk: &[u8]) -> &[u8] {
+    &chunk[4..8]
+}
+
+/// `encoded` with the `KEPT_CHUNKS` of `original` inserted after its header. The CRCs of chunks only cover their
+/// type and data, so they can be copied as they are
+fn with_kept_chunks(original: &[u8], encoded: &[u8]) -> Option<Vec<u8>> {
+    let original = chunks(original)?;
+    let encoded = chunks(encoded)?;
+    let (header, rest) = encoded.split_first()?;
+    let mut png = PNG_SIGNATURE.to_vec();
+    png.extend_from_slice(header);
+    for chunk in original.iter().filter(|v| KEPT_CHUNKS.contains(&chunk_type(v))) {
+        png.extend_from_slice(chunk);
+    }
+    for chunk in rest.iter().filter(|v| !KEPT_CHUNKS.contains(&chunk_type(v))) {
+        png.extend_from_slice(chunk);
+    }
+    Some(png)
+}
+
+/// Re-encode the png at `path` with maximum compression, if that makes it smaller. Text chunks, e.g. an embedded
+/// diagram, and the resolution are kept. Returns the new size in KB
+pub fn optimize_png(path: &Path) -> Result<u64, image::ImageError> {
+    let original = fs::read(path)?;
+    let image = image::load_from_memory_with_format(&original, ImageFormat::Png)?;
+    let mut encoded = Vec::new();
+    image.write_with_encoder(PngEncoder::new_with_quality(&mut encoded, CompressionType::Best, FilterType::Adaptive))?;
+    let optimized = with_kept_chunks(&original, &encoded).ok_or_else(|| {
+        image::ImageError::Decoding(image::error::DecodingError::new(ImageFormat::Png.into(), "malformed png chunks"))
+    })?;
+    if optimized.len() < original.len() {
+        //write to a sibling first, so that an interrupted write does not destroy the output
+        let optimized_path = path.with_extension("optimized.png");
+        fs::write(&optimized_path, &optimized)?;
+        fs::rename(&optimized_path, path)?;
+    }
+    Ok(size_kb(path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reduced_scale() {
+        let flags = |v: &str| -> Vec<String> { v.split(' ').map(|v| v.to_string()).collect() };
+        assert_eq!(reduced_scale(&flags("-x -f png -s 5"), 400, 100), Some((2.25, flags("-x -f png -s 2.25"))));
+        assert_eq!(reduced_scale(&flags("-x -f png"), 121, 100), Some((0.81, flags("-x -f png -s 0.81"))));
+        assert_eq!(reduced_scale(&flags("-x -f png -s 1"), 400, 100), None);
+    }
+
+    #[test]
+    fn test_optimize_png_keeps_embedded_diagram() {
+        let path = std::env::temp_dir().join(format!("drawio-builder-budget-{}.png", std::process::id()));
+        let image = image::RgbImage::from_fn(64, 64, |x, _| image::Rgb([(x % 2) as u8 * 255, 0, 0]));
+        let mut encoded = Vec::new();
+        image
+            .write_with_encoder(PngEncoder::new_with_quality(&mut encoded, CompressionType::Fast, FilterType::NoFilter))
+            .unwrap();
+        //a tEXt chunk as written by drawio with --embed-diagram
+        let data = b"mxfile\x00<mxfile/>";
+        let mut text = (data.len() as u32).to_be_bytes().to_vec();
+        text.extend_from_slice(b"tEXt");
+        text.extend_from_slice(data);
+        let mut crc = flate2::Crc::new();
+        crc.update(&text[4..]);
+        text.extend_from_slice(&crc.sum().to_be_bytes());
+        let mut chunks = chunks(&encoded).unwrap();
+        chunks.insert(1, &text);
+        let original = [PNG_SIGNATURE, &chunks.concat()].concat();
+        fs::write(&path, &original).unwrap();
+
+        optimize_png(&path).unwrap();
+        let optimized = fs::read(&path).unwrap();
+        assert!(optimized.len() < original.len());
+        assert!(optimized.windows(text.len()).any(|v| v == text.as_slice()));
+        assert!(image::load_from_memory(&optimized).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
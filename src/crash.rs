@@ -0,0 +1,103 @@
+//! Electron writes crash dumps and logs to OS specific folders instead of stderr, which is often empty after a
+//! hard crash. If drawio crashes, we collect the ones written during its run, so that the error report points at them
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::{Duration, SystemTime};
+
+/// True if the process was terminated by a signal (Unix) or an unhandled exception (Windows) instead of exiting
+#[cfg(unix)]
+pub fn crashed(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().is_some()
+}
+
+#[cfg(windows)]
+pub fn crashed(status: &ExitStatus) -> bool {
+    //NTSTATUS error codes, e.g. 0xC0000005 for access violations
+    status.code().is_some_and(|v| v as u32 >= 0xC000_0000)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn crashed(_status: &ExitStatus) -> bool {
+    false
+}
+
+fn env_dir(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name).map(PathBuf::from)
+}
+
+/// Electron's user data folder of drawio desktop
+fn user_data_dir() -> Option<PathBuf> {
+    let config_dir = if cfg!(target_os = "macos") {
+        env_dir("HOME")?.join("Library").join("Application Support")
+    } else if cfg!(windows) {
+        env_dir("APPDATA")?
+    } else {
+        env_dir("XDG_CONFIG_HOME").or_else(|| Some(env_dir("HOME")?.join(".config")))?
+    };
+    Some(config_dir.join("draw.io"))
+}
+
+/// Files that may contain crash dumps or logs of drawio
+fn candidates() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    if let Some(user_data) = user_data_dir() {
+        let crashpad = user_data.join("Crashpad");
+        dirs.extend(["completed", "pending", "reports"].map(|v| crashpad.join(v)));
+        files.push(user_data.join("chrome_debug.log"));
+    }
+    //chromium writes its log here if set, e.g. together with --enable-logging
+    files.extend(env_dir("CHROME_LOG_FILE"));
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        files.extend(entries.flatten().map(|v| v.path()));
+    }
+    if cfg!(target_os = "macos") {
+        //the system crash reporter writes reports of all applications to the same folder
+        let reports = env_dir("HOME").map(|v| v.join("Library").join("Logs").join("DiagnosticReports"));
+        if let Some(Ok(entries)) = reports.map(fs::read_dir) {
+            files.extend(
+                entries
+                    .flatten()
+                    .map(|v| v.path())
+                    .filter(|v| v.file_name().is_some_and(|v| v.to_string_lossy().starts_with("draw.io"))),
+            );
+        }
+    }
+    files
+}
+
+/// Crash dumps and logs that were written since `since`, i.e. during the run of a crashed drawio process.
+/// With parallel jobs, this may include artifacts of other drawio processes that crashed at the same time
+pub fn collect_artifacts(since: SystemTime) -> Vec<PathBuf> {
+    //file systems store modification times with a coarse resolution, up to two seconds on FAT
+    let since = since - Duration::from_secs(2);
+    let mut artifacts: Vec<PathBuf> = candidates()
+        .into_iter()
+        .filter(|v| {
+            v.metadata()
+                .and_then(|v| v.modified())
+                .is_ok_and(|v| v >= since)
+        })
+        .collect();
+    artifacts.sort();
+    artifacts
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn test_crashed() {
+        //raw wait statuses: terminated by SIGSEGV, exited with code 1
+        assert!(crashed(&ExitStatus::from_raw(11)));
+        assert!(!crashed(&ExitStatus::from_raw(1 << 8)));
+    }
+}
@@ -0,0 +1,129 @@
+//! `decompress` subcommand: rewrite compressed .drawio files as plain XML with sorted attributes, so that they can be
+//! diffed in git
+
+use clap::Args;
+use snafu::prelude::*;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+use crate::diagram::DrawioDocument;
+use crate::{find_drawio_files, AppError};
+
+#[derive(Args)]
+pub struct DecompressArgs {
+    ///Path to a .drawio file or a folder with .drawio files
+    #[arg(short, long, default_value = "./")]
+    input: String,
+
+    ///Write all files to this folder instead of rewriting compressed files in place
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+
+    ///Do not write anything, only fail if there are compressed files or files with unsorted attributes, e.g. in CI
+    #[arg(long, default_value = "false")]
+    check: bool,
+}
+
+pub fn run(args: &DecompressArgs) -> Result<(), AppError> {
+    if let Some(out_dir) = &args.out_dir {
+        create_dir_all(out_dir).whatever_context::<String, AppError>(format!("Failed to create output dir at {:?}", out_dir))?;
+    }
+
+    let mut outdated_files = Vec::new();
+    for file in find_drawio_files(&args.input)? {
+        let mut document = DrawioDocument::load(&file)
+            .whatever_context::<String, AppError>(format!("failed to parse file {:?}", &file))?;
+        //a later save in drawio may reorder the attributes, which shows up in the diff like a change
+        let outdated = document.is_compressed() || !document.attributes_sorted();
+        if outdated {
+            outdated_files.push(file.display().to_string());
+        }
+        if args.check {
+            continue;
+        }
+
+        let target = match &args.out_dir {
+            Some(out_dir) => out_dir.join(file.file_name().unwrap()),
+            None if outdated => file.clone(),
+            None => continue,
+        };
+        document.disable_compression();
+        document.sort_attributes();
+        document
+            .save(&target)
+            .whatever_context::<String, AppError>(format!("failed to write {:?}", &target))?;
+        println!("{} -> {}", file.display(), target.display());
+    }
+
+    if args.check && !outdated_files.is_empty() {
+        whatever!(
+            "The following files are compressed or have unsorted attributes. Run drawio-builder decompress : {}",
+            outdated_files.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+    use std::fs;
+    use std::path::Path;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: DecompressArgs,
+    }
+
+    fn decompress(args: &[&str]) -> Result<(), AppError> {
+        run(&Cli::parse_from(["decompress"].iter().chain(args)).args)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-decompress-{}", std::process::id()));
+        let out_dir = dir.to_str().unwrap();
+        decompress(&["-i", "test-data/compressed.drawio", "--out-dir", out_dir]).unwrap();
+        let original = DrawioDocument::load(Path::new("test-data/compressed.drawio")).unwrap();
+        let decompressed_path = dir.join("compressed.drawio");
+        let decompressed = DrawioDocument::load(&decompressed_path).unwrap();
+        assert!(!decompressed.is_compressed());
+        assert!(decompressed.attributes_sorted());
+        let texts = |document: &DrawioDocument| -> Vec<String> {
+            document.pages()[0].cells().iter().map(|v| v.text()).collect()
+        };
+        assert_eq!(texts(&original), texts(&decompressed));
+
+        //decompressing again does not change anything, in particular not the attribute order
+        let first = fs::read(&decompressed_path).unwrap();
+        decompress(&["-i", decompressed_path.to_str().unwrap(), "--out-dir", out_dir]).unwrap();
+        assert_eq!(first, fs::read(&decompressed_path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-decompress-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(decompress(&["-i", "test-data/compressed.drawio", "--check"]).is_err());
+
+        let file = dir.join("plain.drawio");
+        let mxfile = |file_attributes: &str, diagram_attributes: &str| {
+            format!(
+                "<mxfile {}><diagram {}><mxGraphModel><root><mxCell id=\"0\"/></root></mxGraphModel></diagram></mxfile>",
+                file_attributes, diagram_attributes
+            )
+        };
+        fs::write(&file, mxfile(r#"compressed="false" host="Electron""#, r#"id="p" name="Page-1""#)).unwrap();
+        let path = file.to_str().unwrap();
+        decompress(&["-i", path, "--check"]).unwrap();
+        //uncompressed, but the attributes are not sorted
+        fs::write(&file, mxfile(r#"compressed="false" host="Electron""#, r#"name="Page-1" id="p""#)).unwrap();
+        assert!(decompress(&["-i", path, "--check"]).is_err());
+        decompress(&["-i", path]).unwrap();
+        decompress(&["-i", path, "--check"]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
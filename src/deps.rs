@@ -0,0 +1,135 @@
+//! Figures may reference other local files, e.g. link to another .drawio file or embed an image by its path.
+//! Their outputs are outdated if any of these (transitively) changed, not only if the figure itself changed
+
+use percent_encoding::percent_decode_str;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::diagram::{has_uri_scheme, DrawioDocument};
+
+/// Transitive dependencies of the input files of a build
+#[derive(Default)]
+pub struct Dependencies {
+    deps: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+/// Path of a `file://` url, e.g. "/home/a/b.drawio" for "file:///home/a/b.drawio" or "C:/a/b.drawio" for "file:///C:/a/b.drawio"
+fn file_url_path(url: &str) -> Option<&str> {
+    let path = url.strip_prefix("file://")?;
+    //the host is usually empty, but may be "localhost"
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let is_drive = |v: &str| v.as_bytes().get(2) == Some(&b':') && v.as_bytes()[1].is_ascii_alphabetic();
+    Some(if is_drive(path) { &path[1..] } else { path })
+}
+
+/// Existing local files referenced by `document`, which has been loaded from the canonical path `file`.
+/// Returns canonical paths. Urls other than `file://`, embedded data and references to the file itself are ignored
+fn local_references(file: &Path, document: &DrawioDocument) -> Vec<PathBuf> {
+    let dir = file.parent().unwrap_or(Path::new(""));
+    document
+        .references()
+        .iter()
+        .filter_map(|v| match file_url_path(v) {
+            Some(path) => Some(path),
+            None if has_uri_scheme(v) => None,
+            None => Some(v.as_str()),
+        })
+        .filter_map(|v| {
+            //links may point to a page within the file
+            let path = v.split(['#', '?']).next()?;
+            let path = percent_decode_str(path).decode_utf8().ok()?;
+            (!path.is_empty()).then(|| dir.join(path.as_ref()))
+        })
+        .filter(|v| v.is_file())
+        .filter_map(|v| v.canonicalize().ok())
+        .filter(|v| v != file)
+        .collect()
+}
+
+impl Dependencies {
+    /// Parse the references of `files` and, recursively, of referenced .drawio files.
+    /// Files that fail to parse are treated as having no dependencies, the build reports them later on.
+    /// Dependencies are compared and returned as canonical paths, so that different spellings of a path match
+    pub fn resolve(files: &[PathBuf]) -> Self {
+        //keyed by canonical path
+        let mut direct: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        let mut pending: Vec<PathBuf> = files.iter().filter_map(|v| v.canonicalize().ok()).collect();
+        while let Some(file) = pending.pop() {
+            if direct.contains_key(&file) {
+                continue;
+            }
+            let references = match DrawioDocument::load(&file) {
+                Ok(document) => local_references(&file, &document),
+                Err(_) => Vec::new(),
+            };
+            pending.extend(references.iter().filter(|v| v.extension().is_some_and(|v| v == "drawio")).cloned());
+            direct.insert(file, references);
+        }
+
+        let mut deps = HashMap::new();
+        for file in files {
+            let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+            let mut transitive: Vec<PathBuf> = Vec::new();
+            let mut pending = direct.get(&canonical).cloned().unwrap_or_default();
+            //the visited check keeps cyclic references from looping forever
+            while let Some(dep) = pending.pop() {
+                if dep == canonical || transitive.contains(&dep) {
+                    continue;
+                }
+                pending.extend(direct.get(&dep).cloned().unwrap_or_default());
+                transitive.push(dep);
+            }
+            transitive.sort();
+            deps.insert(file.clone(), transitive);
+        }
+        Dependencies { deps }
+    }
+
+    /// Transitive dependencies of the input file `file`
+    pub fn of(&self, file: &Path) -> &[PathBuf] {
+        self.deps.get(file).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// `file` followed by its dependencies, i.e. all files that affect its outputs
+    pub fn sources(&self, file: &Path) -> Vec<PathBuf> {
+        std::iter::once(file.to_path_buf()).chain(self.of(file).iter().cloned()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn linking(link: &str, image: &str) -> String {
+        format!(
+            r#"<mxfile><diagram id="p" name="Page-1"><mxGraphModel><root><mxCell id="0"/><mxCell id="1" parent="0"/>
+            <UserObject id="2" label="see" link="{}"><mxCell style="text;" vertex="1" parent="1"/></UserObject>
+            <mxCell id="3" style="shape=image;image={};" vertex="1" parent="1"/>
+            </root></mxGraphModel></diagram></mxfile>"#,
+            link, image
+        )
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-deps-{}", std::process::id()));
+        fs::create_dir_all(dir.join("img")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        fs::write(dir.join("a.drawio"), linking("b%20c.drawio#page-2", "img/logo.svg")).unwrap();
+        //references back to a are ignored, however they are spelled
+        fs::write(dir.join("b c.drawio"), linking("./a.drawio", "https://example.com/x.png")).unwrap();
+        fs::write(dir.join("img/logo.svg"), "<svg/>").unwrap();
+        fs::write(dir.join("d.drawio"), linking("missing.drawio", "data:image/png,abc")).unwrap();
+        let url = format!("file://{}", dir.join("img/logo.svg").display()).replace(' ', "%20");
+        fs::write(dir.join("e.drawio"), linking(&url, "img/../img/logo.svg")).unwrap();
+
+        let files = [dir.join("a.drawio"), dir.join("d.drawio"), dir.join("img/../e.drawio")];
+        let deps = Dependencies::resolve(&files);
+        assert_eq!(deps.of(&files[0]), [dir.join("b c.drawio"), dir.join("img/logo.svg")]);
+        assert!(deps.of(&files[1]).is_empty());
+        assert_eq!(deps.of(&files[2]), [dir.join("img/logo.svg")]);
+        assert_eq!(file_url_path("file:///C:/a/b.drawio"), Some("C:/a/b.drawio"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1031 @@
+//! Parsing and rewriting of .drawio files.
+//!
+//! Compressed pages are inflated while parsing, so all other code only has to deal with
+//! the plain `mxGraphModel` XML.
+
+use base64::prelude::*;
+use flate2::read::DeflateDecoder;
+use percent_encoding::percent_decode_str;
+use snafu::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use xmltree::{Element, EmitterConfig, XMLNode};
+
+#[derive(Debug, Snafu)]
+pub enum DiagramError {
+    #[snafu(display("failed to access {path:?}"))]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("failed to parse xml"))]
+    Xml { source: xmltree::ParseError },
+
+    #[snafu(display("failed to serialize xml"))]
+    Serialize { source: xmltree::Error },
+
+    #[snafu(display("failed to decompress page {page} : {message}"))]
+    Decompress { page: usize, message: String },
+
+    #[snafu(display("not a drawio file : {message}"))]
+    Malformed { message: String },
+}
+
+/// In-memory representation of a .drawio file
+#[derive(Clone)]
+pub struct DrawioDocument {
+    ///The `mxfile` element. All pages are stored uncompressed
+    root: Element,
+    ///True if at least one page was compressed in the parsed file
+    compressed: bool,
+}
+
+impl DrawioDocument {
+    pub fn load(path: &Path) -> Result<Self, DiagramError> {
+        let file = File::open(path).context(IoSnafu { path })?;
+        Self::parse(BufReader::new(file))
+    }
+
+    pub fn parse<R: Read>(reader: R) -> Result<Self, DiagramError> {
+        let mut root = Element::parse(reader).context(XmlSnafu)?;
+        ensure!(
+            root.name == "mxfile",
+            MalformedSnafu {
+                message: format!("unexpected root element {}", root.name)
+            }
+        );
+        let mut compressed = false;
+        for (idx, diagram) in child_elements_mut(&mut root, "diagram").enumerate() {
+            compressed |= inflate_page(idx, diagram)?;
+        }
+        Ok(DrawioDocument { root, compressed })
+    }
+
+    /// Empty, uncompressed document without pages
+    pub fn new() -> Self {
+        let mut root = Element::new("mxfile");
+        root.attributes
+            .insert("compressed".to_string(), "false".to_string());
+        DrawioDocument {
+            root,
+            compressed: false,
+        }
+    }
+
+    /// Append a copy of `page`, including all of its layers, under the given name.
+    /// The page id is changed if it is already used by another page
+    pub fn push_page(&mut self, page: &Page, name: &str) {
+        let mut diagram = page.diagram.clone();
+        diagram.attributes.insert("name".to_string(), name.to_string());
+        let ids: Vec<String> = self
+            .pages()
+            .iter()
+            .filter_map(|p| p.id().map(str::to_string))
+            .collect();
+        if let Some(id) = page.id().filter(|id| ids.iter().any(|v| v == id)) {
+            let unique_id = (2..)
+                .map(|n| format!("{id}-{n}"))
+                .find(|v| !ids.contains(v))
+                .unwrap();
+            diagram.attributes.insert("id".to_string(), unique_id);
+        }
+        self.root.children.push(XMLNode::Element(diagram));
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Tell drawio to keep the file uncompressed when saving it
+    pub fn disable_compression(&mut self) {
+        self.root
+            .attributes
+            .insert("compressed".to_string(), "false".to_string());
+    }
+
+    /// Remove attributes that drawio updates on every save without changing the diagram and sort
+    /// the remaining attributes of all elements by name
+    pub fn normalize(&mut self) {
+        for name in VOLATILE_FILE_ATTRIBUTES {
+            self.root.attributes.shift_remove(*name);
+        }
+        for diagram in child_elements_mut(&mut self.root, "diagram") {
+            if let Some(model) = diagram.get_mut_child("mxGraphModel") {
+                for name in VOLATILE_MODEL_ATTRIBUTES {
+                    model.attributes.shift_remove(*name);
+                }
+            }
+        }
+        self.sort_attributes();
+    }
+
+    /// Sort the attributes of all elements by name, so that the XML does not depend on the order drawio wrote them in
+    pub fn sort_attributes(&mut self) {
+        sort_attributes(&mut self.root);
+    }
+
+    /// True if the attributes of all elements are sorted by name, i.e. `sort_attributes` does not change the document
+    pub fn attributes_sorted(&self) -> bool {
+        attributes_sorted(&self.root)
+    }
+
+    pub fn pages(&self) -> Vec<Page<'_>> {
+        self.root
+            .children
+            .iter()
+            .filter_map(|v| v.as_element())
+            .filter(|e| e.name == "diagram")
+            .map(Page::new)
+            .collect()
+    }
+
+    /// Only show the given layers (by index) on every page. All other layers are hidden
+    pub fn set_visible_layers(&mut self, layers: &[usize]) {
+        for diagram in child_elements_mut(&mut self.root, "diagram") {
+            let Some(graph_root) = graph_root_mut(diagram) else {
+                continue;
+            };
+            for (idx, layer) in layers_mut(graph_root).enumerate() {
+                if layers.contains(&idx) {
+                    layer.attributes.shift_remove("visible");
+                } else {
+                    layer.attributes.insert("visible".to_string(), "0".to_string());
+                }
+            }
+        }
+    }
+
+    /// Names of all `{{name}}` placeholders in the labels of all pages
+    pub fn placeholders(&self) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+        for page in self.pages() {
+            for cell in page.cells() {
+                names.extend(placeholders(cell.value()).into_iter().map(|(_, name)| name.to_string()));
+            }
+        }
+        names
+    }
+
+    /// Links and image paths of all cells, e.g. "other.drawio#page" or "img/logo.svg".
+    /// May contain urls and embedded data urls as well
+    pub fn references(&self) -> BTreeSet<String> {
+        let mut references = BTreeSet::new();
+        for page in self.pages() {
+            for cell in page.cells() {
+                references.extend(cell.link().map(str::to_string));
+                references.extend(cell.style_value("image").map(str::to_string));
+            }
+        }
+        references
+    }
+
+    /// Make relative links and image paths absolute by prepending `dir`, the folder of the file. Drawio resolves them
+    /// relative to the file it exports, so they would break in a copy of the file in another folder
+    pub fn resolve_relative_references(&mut self, dir: &Path) {
+        let resolve = |reference: &str| -> Option<String> {
+            let relative = !reference.is_empty()
+                && !has_uri_scheme(reference)
+                && !reference.starts_with('#')
+                && Path::new(reference).is_relative();
+            relative.then(|| dir.join(reference).to_string_lossy().into_owned())
+        };
+        for diagram in child_elements_mut(&mut self.root, "diagram") {
+            let Some(graph_root) = graph_root_mut(diagram) else {
+                continue;
+            };
+            for elem in graph_root.children.iter_mut().filter_map(|v| v.as_mut_element()) {
+                if let Some(link) = elem.attributes.get("link").and_then(|v| resolve(v)) {
+                    elem.attributes.insert("link".to_string(), link);
+                }
+                let Some(cell) = cell_of_mut(elem) else {
+                    continue;
+                };
+                let Some(style) = cell.attributes.get("style") else {
+                    continue;
+                };
+                let resolved: Vec<String> = style
+                    .split(';')
+                    .map(|entry| match entry.split_once('=') {
+                        Some(("image", value)) => resolve(value).map(|v| format!("image={}", v)).unwrap_or(entry.to_string()),
+                        _ => entry.to_string(),
+                    })
+                    .collect();
+                cell.attributes.insert("style".to_string(), resolved.join(";"));
+            }
+        }
+    }
+
+    /// Replace `{{name}}` placeholders in all labels with the value of `name` in `variables`.
+    /// Placeholders without a value are kept
+    pub fn substitute_placeholders(&mut self, variables: &BTreeMap<String, String>) {
+        self.map_labels(|value, html| {
+            let mut result = String::new();
+            let mut last = 0;
+            for (range, name) in placeholders(value) {
+                let Some(replacement) = variables.get(name) else {
+                    continue;
+                };
+                result += &value[last..range.start];
+                result += &if html { escape_html(replacement) } else { replacement.clone() };
+                last = range.end;
+            }
+            (last > 0).then(|| result + &value[last..])
+        });
+    }
+
+    /// Replace every label whose plain text is a key of `translations` with the translated text.
+    /// Formatting within translated html labels is lost
+    pub fn translate(&mut self, translations: &BTreeMap<String, String>) {
+        self.map_labels(|value, html| {
+            let text = if html { html_to_text(value) } else { value.trim().to_string() };
+            let translated = translations.get(&text)?;
+            Some(if html { escape_html(translated) } else { translated.clone() })
+        });
+    }
+
+    /// Replace the label of every cell for which `f` returns a new label. The second argument
+    /// of `f` is true if the label is html
+    fn map_labels(&mut self, f: impl Fn(&str, bool) -> Option<String>) {
+        for diagram in child_elements_mut(&mut self.root, "diagram") {
+            let Some(graph_root) = graph_root_mut(diagram) else {
+                continue;
+            };
+            for elem in graph_root.children.iter_mut().filter_map(|v| v.as_mut_element()) {
+                let Some(cell) = Cell::new(elem) else {
+                    continue;
+                };
+                let Some(label) = f(cell.value(), cell.style_value("html") == Some("1")) else {
+                    continue;
+                };
+                let attr = if elem.name == "mxCell" { "value" } else { "label" };
+                elem.attributes.insert(attr.to_string(), label);
+            }
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), DiagramError> {
+        //drawio does not write a declaration either
+        let config = EmitterConfig::new()
+            .perform_indent(true)
+            .write_document_declaration(false);
+        self.root
+            .write_with_config(writer, config)
+            .context(SerializeSnafu)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), DiagramError> {
+        let file = File::create(path).context(IoSnafu { path })?;
+        self.write(file)
+    }
+}
+
+impl Default for DrawioDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A single page (`diagram` element) of a drawio file
+pub struct Page<'a> {
+    diagram: &'a Element,
+    ///All cells of the page, in document order
+    cells: Vec<Cell<'a>>,
+    root_id: Option<&'a str>,
+    ///Maps cell ids to the id of their parent
+    parents: HashMap<&'a str, &'a str>,
+    ///Maps cell ids to their index in `cells`
+    cell_indices: HashMap<&'a str, usize>,
+    ///Maps layer ids to their layer index
+    layer_indices: HashMap<&'a str, usize>,
+}
+
+impl<'a> Page<'a> {
+    fn new(diagram: &'a Element) -> Self {
+        let cells: Vec<Cell> = diagram
+            .get_child("mxGraphModel")
+            .and_then(|m| m.get_child("root"))
+            .map(|root| {
+                root.children
+                    .iter()
+                    .filter_map(|v| Cell::new(v.as_element()?))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let root_id = cells.iter().find(|c| c.parent().is_none()).and_then(|c| c.id());
+        let parents = cells
+            .iter()
+            .filter_map(|c| Some((c.id()?, c.parent()?)))
+            .collect();
+        let cell_indices = cells
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, c)| Some((c.id()?, idx)))
+            .collect();
+        let layer_indices = cells
+            .iter()
+            .filter(|c| root_id.is_some() && c.parent() == root_id)
+            .filter_map(Cell::id)
+            .enumerate()
+            .map(|(idx, id)| (id, idx))
+            .collect();
+        Page {
+            diagram,
+            cells,
+            root_id,
+            parents,
+            cell_indices,
+            layer_indices,
+        }
+    }
+
+    pub fn id(&self) -> Option<&'a str> {
+        self.diagram.attributes.get("id").map(String::as_str)
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.diagram
+            .attributes
+            .get("name")
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    pub fn cells(&self) -> &[Cell<'a>] {
+        &self.cells
+    }
+
+    /// Page size as configured in the page setup
+    pub fn page_size(&self) -> Option<(f64, f64)> {
+        let model = self.diagram.get_child("mxGraphModel")?;
+        let width = model.attributes.get("pageWidth")?.parse().ok()?;
+        let height = model.attributes.get("pageHeight")?.parse().ok()?;
+        Some((width, height))
+    }
+
+    /// Layers in document order, i.e. the order drawio uses for layer indices
+    pub fn layers(&self) -> Vec<&Cell<'a>> {
+        self.cells
+            .iter()
+            .filter(|c| self.root_id.is_some() && c.parent() == self.root_id)
+            .collect()
+    }
+
+    /// Index of the layer that (transitively) contains `cell`.
+    /// Returns None for the root cell, layers and cells with a broken parent chain
+    pub fn layer_index(&self, cell: &Cell) -> Option<usize> {
+        let mut current = cell.parent()?;
+        //bound the number of steps in case of cyclic parents
+        for _ in 0..=self.cells.len() {
+            if let Some(idx) = self.layer_indices.get(current) {
+                return Some(*idx);
+            }
+            current = self.parents.get(current)?;
+        }
+        None
+    }
+
+    /// False if `cell` or one of the groups or containers it is part of is hidden.
+    /// The visibility of layers is not considered, as builds select the visible layers themselves
+    pub fn is_visible(&self, cell: &Cell) -> bool {
+        if !cell.is_visible() {
+            return false;
+        }
+        let mut current = cell.parent();
+        //bound the number of steps in case of cyclic parents
+        for _ in 0..=self.cells.len() {
+            let Some(id) = current.filter(|id| !self.layer_indices.contains_key(id)) else {
+                break;
+            };
+            let Some(parent) = self.cell(id) else {
+                break;
+            };
+            if !parent.is_visible() {
+                return false;
+            }
+            current = parent.parent();
+        }
+        true
+    }
+
+    /// Cell with the given id
+    fn cell(&self, id: &str) -> Option<&Cell<'a>> {
+        self.cell_indices.get(id).map(|idx| &self.cells[*idx])
+    }
+
+    /// Bounding box of `cell` in page coordinates. Vertex geometries are relative to their parent vertex (e.g. a group).
+    /// None for edge labels, whose geometry is relative to the path of their edge
+    pub fn absolute_geometry(&self, cell: &Cell) -> Option<Geometry> {
+        let mut geometry = cell.geometry()?;
+        if cell.has_relative_geometry() || cell.parent().and_then(|id| self.cell(id)).is_some_and(Cell::is_edge) {
+            return None;
+        }
+        let mut current = cell.parent();
+        //bound the number of steps in case of cyclic parents
+        for _ in 0..=self.cells.len() {
+            let Some(parent) = current.and_then(|id| self.cell(id)) else {
+                break;
+            };
+            if parent.is_vertex() {
+                if let Some(offset) = parent.geometry() {
+                    geometry.x += offset.x;
+                    geometry.y += offset.y;
+                }
+            }
+            current = parent.parent();
+        }
+        Some(geometry)
+    }
+
+    /// Text labels of all visible cells on the given layers, in document order
+    pub fn text_labels(&self, layers: &[usize]) -> Vec<String> {
+        self.cells
+            .iter()
+            .filter(|c| self.is_visible(c))
+            .filter(|c| self.layer_index(c).is_some_and(|idx| layers.contains(&idx)))
+            .map(Cell::text)
+            .filter(|text| !text.is_empty())
+            .collect()
+    }
+}
+
+/// A cell of a page. Cells with custom properties are wrapped in an `object` or `UserObject` element,
+/// which holds the id and the label while the `mxCell` child holds the rest
+pub struct Cell<'a> {
+    wrapper: &'a Element,
+    cell: &'a Element,
+}
+
+impl<'a> Cell<'a> {
+    fn new(elem: &'a Element) -> Option<Self> {
+        Some(Cell {
+            wrapper: elem,
+            cell: cell_of(elem)?,
+        })
+    }
+
+    pub fn id(&self) -> Option<&'a str> {
+        self.wrapper.attributes.get("id").map(String::as_str)
+    }
+
+    pub fn parent(&self) -> Option<&'a str> {
+        self.cell.attributes.get("parent").map(String::as_str)
+    }
+
+    /// Raw label, may contain html markup
+    pub fn value(&self) -> &'a str {
+        let attr = if self.wrapper.name == "mxCell" {
+            "value"
+        } else {
+            "label"
+        };
+        self.wrapper
+            .attributes
+            .get(attr)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Target of the cell's link, if any. Links are stored on the wrapping `UserObject`
+    pub fn link(&self) -> Option<&'a str> {
+        self.wrapper.attributes.get("link").map(String::as_str)
+    }
+
+    pub fn style(&self) -> &'a str {
+        self.cell
+            .attributes
+            .get("style")
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Value of `key` in the cell's style, e.g. "Helvetica" for `fontFamily`
+    pub fn style_value(&self, key: &str) -> Option<&'a str> {
+        self.style().split(';').find_map(|entry| {
+            let (k, v) = entry.split_once('=')?;
+            (k == key).then_some(v)
+        })
+    }
+
+    /// Font families used for the label, including fonts set via html markup.
+    /// Empty if the cell has no label
+    pub fn font_families(&self) -> Vec<String> {
+        if self.text().is_empty() {
+            return Vec::new();
+        }
+        let mut families = vec![self
+            .style_value("fontFamily")
+            .unwrap_or(DEFAULT_FONT_FAMILY)
+            .to_string()];
+        if self.style_value("html") == Some("1") {
+            //markup within the html's attributes is entity encoded once more
+            let value = decode_entities(self.value());
+            let lists = value
+                .split("face=")
+                .skip(1)
+                .filter_map(|v| {
+                    let quote = v.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+                    v[1..].split(quote).next()
+                })
+                .chain(
+                    value
+                        .split("font-family:")
+                        .skip(1)
+                        .filter_map(|v| v.split([';', '>']).next()),
+                );
+            for list in lists {
+                for family in list.split(',') {
+                    let family = family
+                        .trim()
+                        .trim_matches(['"', '\''])
+                        .to_string();
+                    if !family.is_empty() && !families.contains(&family) {
+                        families.push(family);
+                    }
+                }
+            }
+        }
+        families
+    }
+
+    pub fn is_vertex(&self) -> bool {
+        self.cell.attributes.get("vertex").map(String::as_str) == Some("1")
+    }
+
+    pub fn is_edge(&self) -> bool {
+        self.cell.attributes.get("edge").map(String::as_str) == Some("1")
+    }
+
+    /// True if the geometry is relative to the parent, e.g. the position of an edge label along its edge
+    pub fn has_relative_geometry(&self) -> bool {
+        self.cell
+            .get_child("mxGeometry")
+            .and_then(|v| v.attributes.get("relative"))
+            .map(String::as_str)
+            == Some("1")
+    }
+
+    /// Geometry relative to the parent cell. Missing coordinates default to zero, like in drawio
+    pub fn geometry(&self) -> Option<Geometry> {
+        let geometry = self.cell.get_child("mxGeometry")?;
+        let attr = |name: &str| {
+            geometry
+                .attributes
+                .get(name)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0.0)
+        };
+        Some(Geometry {
+            x: attr("x"),
+            y: attr("y"),
+            width: attr("width"),
+            height: attr("height"),
+        })
+    }
+
+    /// False if the cell itself is hidden. See `Page::is_visible` to also consider the groups it is part of
+    pub fn is_visible(&self) -> bool {
+        self.cell.attributes.get("visible").map(String::as_str) != Some("0")
+    }
+
+    /// Label as plain text, i.e. without html markup
+    pub fn text(&self) -> String {
+        if self.style_value("html") == Some("1") {
+            html_to_text(self.value())
+        } else {
+            self.value().trim().to_string()
+        }
+    }
+}
+
+/// Font used by drawio if the style does not specify one
+pub const DEFAULT_FONT_FAMILY: &str = "Helvetica";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geometry {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Cells are equal if their XML, including geometry and custom properties, is equal
+impl PartialEq for Cell<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.wrapper == other.wrapper
+    }
+}
+
+/// Strip tags and decode entities. Line breaking tags are replaced with newlines
+fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text += &decode_entities(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = rest[start + 1..start + end]
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if matches!(tag.as_str(), "br" | "div" | "p" | "li" | "tr") && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text += &decode_entities(rest);
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Escape `text` for use in a html label
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>")
+}
+
+/// Byte range and name of all `{{name}}` placeholders in `text`. Names may consist of
+/// alphanumeric characters, "_", "-" and ".", surrounded by optional whitespace
+fn placeholders(text: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find("{{").map(|v| v + offset) {
+        let Some(end) = text[start..].find("}}").map(|v| v + start + 2) else {
+            break;
+        };
+        let name = text[start + 2..end - 2].trim();
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c));
+        if valid {
+            result.push((start..end, name));
+            offset = end;
+        } else {
+            offset = start + 1;
+        }
+    }
+    result
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        result += &rest[..start];
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "nbsp" => ' ',
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                v if v.starts_with("#x") => char::from_u32(u32::from_str_radix(&v[2..], 16).ok()?)?,
+                v if v.starts_with('#') => char::from_u32(v[1..].parse().ok()?)?,
+                _ => return None,
+            };
+            Some((c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result + rest
+}
+
+/// Attributes of the `mxfile` element that only describe when and with which editor the file was saved
+const VOLATILE_FILE_ATTRIBUTES: &[&str] = &["modified", "host", "agent", "etag", "version"];
+/// Attributes of the `mxGraphModel` element that only store the scroll position of the editor
+const VOLATILE_MODEL_ATTRIBUTES: &[&str] = &["dx", "dy"];
+
+/// True if `reference` starts with a URI scheme, e.g. "https:", "mailto:" or "data:". Windows drive letters like
+/// in "C:/figures" are no scheme, as schemes have at least two characters
+pub fn has_uri_scheme(reference: &str) -> bool {
+    let Some((scheme, _)) = reference.split_once(':') else {
+        return false;
+    };
+    let mut chars = scheme.chars();
+    scheme.len() > 1
+        && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+}
+
+fn sort_attributes(elem: &mut Element) {
+    elem.attributes.sort_keys();
+    for child in elem.children.iter_mut().filter_map(|v| v.as_mut_element()) {
+        sort_attributes(child);
+    }
+}
+
+fn attributes_sorted(elem: &Element) -> bool {
+    elem.attributes.keys().is_sorted()
+        && elem
+            .children
+            .iter()
+            .filter_map(|v| v.as_element())
+            .all(attributes_sorted)
+}
+
+fn child_elements_mut<'a>(
+    parent: &'a mut Element,
+    name: &'a str,
+) -> impl Iterator<Item = &'a mut Element> + 'a {
+    parent.children.iter_mut().filter_map(move |node| match node {
+        XMLNode::Element(e) if e.name == name => Some(e),
+        _ => None,
+    })
+}
+
+///Replace the compressed text content of a `diagram` element with the parsed `mxGraphModel`.
+/// Returns true if the page was compressed
+fn inflate_page(page: usize, diagram: &mut Element) -> Result<bool, DiagramError> {
+    if diagram.get_child("mxGraphModel").is_some() {
+        return Ok(false);
+    }
+    let Some(text) = diagram.get_text() else {
+        //empty page
+        return Ok(false);
+    };
+    let model = decompress(text.trim()).map_err(|message| DiagramError::Decompress { page, message })?;
+    let model = Element::parse(model.as_bytes()).context(XmlSnafu)?;
+    diagram.children = vec![XMLNode::Element(model)];
+    Ok(true)
+}
+
+/// Drawio stores compressed pages as base64(deflate_raw(encodeURIComponent(xml)))
+fn decompress(data: &str) -> Result<String, String> {
+    let deflated = BASE64_STANDARD
+        .decode(data)
+        .map_err(|e| format!("invalid base64 : {e}"))?;
+    let mut encoded = String::new();
+    DeflateDecoder::new(deflated.as_slice())
+        .read_to_string(&mut encoded)
+        .map_err(|e| format!("invalid deflate stream : {e}"))?;
+    percent_decode_str(&encoded)
+        .decode_utf8()
+        .map(|v| v.into_owned())
+        .map_err(|e| format!("invalid uri encoding : {e}"))
+}
+
+fn graph_root_mut(diagram: &mut Element) -> Option<&mut Element> {
+    diagram.get_mut_child("mxGraphModel")?.get_mut_child("root")
+}
+
+/// Returns the `mxCell` of each layer in document order, i.e. the order drawio uses for layer indices.
+/// Layers are the children of the root cell, which is the cell without a parent
+fn layers_mut(graph_root: &mut Element) -> impl Iterator<Item = &mut Element> {
+    let root_id = graph_root
+        .children
+        .iter()
+        .filter_map(|v| v.as_element())
+        .find(|e| cell_of(e).is_some_and(|c| !c.attributes.contains_key("parent")))
+        .and_then(|e| e.attributes.get("id").cloned());
+    graph_root.children.iter_mut().filter_map(move |node| {
+        let cell = cell_of_mut(node.as_mut_element()?)?;
+        match (&root_id, cell.attributes.get("parent")) {
+            (Some(root_id), Some(parent)) if root_id == parent => Some(cell),
+            _ => None,
+        }
+    })
+}
+
+/// Cells with custom properties are wrapped in an `object` or `UserObject` element, which holds the id
+/// while the `mxCell` child holds the rest
+fn cell_of(elem: &Element) -> Option<&Element> {
+    match elem.name.as_str() {
+        "mxCell" => Some(elem),
+        "object" | "UserObject" => elem.get_child("mxCell"),
+        _ => None,
+    }
+}
+
+fn cell_of_mut(elem: &mut Element) -> Option<&mut Element> {
+    match elem.name.as_str() {
+        "mxCell" => Some(elem),
+        "object" | "UserObject" => elem.get_mut_child("mxCell"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        //"<mxGraphModel/>" as exported by drawio with compression enabled
+        let got = decompress("UzV2zq1wL0osyPDNT0nNUTVyUzV2BQA=").unwrap();
+        assert_eq!(got, "<mxGraphModel/>");
+    }
+
+    #[test]
+    fn test_parse_compressed() {
+        let doc = DrawioDocument::load(Path::new("test-data/compressed.drawio")).unwrap();
+        assert!(doc.is_compressed());
+        assert_eq!(doc.pages()[0].layers().len(), 1);
+        assert_eq!(doc.pages()[0].text_labels(&[0]), ["hello"]);
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        let got = html_to_text("&nbsp; &nbsp;<b>Hard</b>ware<br>&lt;CVM&gt;<div>a&amp;b</div>");
+        assert_eq!(got, "Hardware\n<CVM>\na&b");
+    }
+
+    #[test]
+    fn test_html_to_text_line_breaks() {
+        assert_eq!(html_to_text("Hello<br>world"), "Hello\nworld");
+        assert_eq!(html_to_text("a<br/>b<BR />c<br><br>d"), "a\nb\nc\nd");
+        //sentences in separate blocks must not run into each other
+        assert_eq!(
+            html_to_text("<div>First sentence.</div><div>Second sentence.</div>Last."),
+            "First sentence.\nSecond sentence.\nLast."
+        );
+        assert_eq!(html_to_text("<p>One</p><ul><li>two</li><li>three</li></ul>"), "One\ntwo\nthree");
+        //inline markup does not break lines, however deeply nested
+        assert_eq!(
+            html_to_text(r#"<div><b>Bold <i>and <u>under</u>lined</i></b> text</div><div><span style="color: red;"><font face="Arial">nested</font></span></div>"#),
+            "Bold and underlined text\nnested"
+        );
+    }
+
+    #[test]
+    fn test_font_families() {
+        let root = Element::parse(
+            r#"<mxCell id="2" value="&lt;font face=&quot;Courier New&quot;&gt;a&lt;/font&gt;&lt;span style=&quot;font-family: &amp;quot;Fira Sans&amp;quot;, sans-serif;&quot;&gt;b&lt;/span&gt;" style="html=1;fontFamily=Times New Roman;" parent="1" vertex="1"/>"#.as_bytes(),
+        )
+        .unwrap();
+        let cell = Cell::new(&root).unwrap();
+        assert_eq!(cell.font_families(), ["Times New Roman", "Courier New", "Fira Sans", "sans-serif"]);
+
+        let root = Element::parse(r#"<mxCell id="2" value="plain" parent="1" vertex="1"/>"#.as_bytes()).unwrap();
+        assert_eq!(Cell::new(&root).unwrap().font_families(), [DEFAULT_FONT_FAMILY]);
+    }
+
+    #[test]
+    fn test_text_labels() {
+        let doc = DrawioDocument::load(Path::new("test-data/nonce-truncation.drawio")).unwrap();
+        let pages = doc.pages();
+        assert_eq!(pages[0].layers().len(), 5);
+        let labels = pages[0].text_labels(&[0]);
+        assert_eq!(labels[..3], ["highest word", "2nd highest word", "lowest word"]);
+        assert!(pages[0].text_labels(&[4]).iter().all(|v| !labels.contains(v)));
+
+        let doc = DrawioDocument::parse(
+            r#"<mxfile><diagram id="p" name="Page-1"><mxGraphModel><root>
+            <mxCell id="0"/><mxCell id="1" parent="0"/>
+            <mxCell id="2" vertex="1" parent="1" visible="0"/>
+            <mxCell id="3" value="hidden by group" vertex="1" parent="2"/>
+            <mxCell id="4" value="shown" vertex="1" parent="1"/>
+            </root></mxGraphModel></diagram></mxfile>"#
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(doc.pages()[0].text_labels(&[0]), ["shown"]);
+    }
+
+    #[test]
+    fn test_set_visible_layers() {
+        let mut doc = DrawioDocument::load(Path::new("test-data/nonce-truncation.drawio")).unwrap();
+        doc.set_visible_layers(&[0, 2]);
+        let mut buf = Vec::new();
+        doc.write(&mut buf).unwrap();
+        let doc = Element::parse(buf.as_slice()).unwrap();
+        let root = doc
+            .get_child("diagram")
+            .and_then(|d| d.get_child("mxGraphModel"))
+            .and_then(|m| m.get_child("root"))
+            .unwrap();
+        let visibility: Vec<Option<&str>> = root
+            .children
+            .iter()
+            .filter_map(|v| v.as_element())
+            .filter(|e| e.attributes.get("parent").map(String::as_str) == Some("0"))
+            .map(|e| e.attributes.get("visible").map(String::as_str))
+            .collect();
+        assert_eq!(visibility, vec![None, Some("0"), None, Some("0"), Some("0")]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut doc = DrawioDocument::load(Path::new("test-data/cvm-motivation.drawio")).unwrap();
+        doc.normalize();
+        let mut formatted = Vec::new();
+        doc.write(&mut formatted).unwrap();
+        let text = String::from_utf8(formatted.clone()).unwrap();
+        assert!(text.starts_with("<mxfile>"));
+        assert!(!text.contains(" dx=\"") && !text.contains("agent="));
+        assert!(text.contains(r#"<mxCell id="1" parent="0" />"#));
+
+        //the storage type is not metadata of the save
+        let mut doc = DrawioDocument::parse(r#"<mxfile host="Electron" type="device"><diagram/></mxfile>"#.as_bytes()).unwrap();
+        doc.normalize();
+        let mut device = Vec::new();
+        doc.write(&mut device).unwrap();
+        assert!(String::from_utf8(device).unwrap().starts_with(r#"<mxfile type="device">"#));
+
+        //formatting is idempotent
+        let mut doc = DrawioDocument::parse(formatted.as_slice()).unwrap();
+        doc.normalize();
+        let mut again = Vec::new();
+        doc.write(&mut again).unwrap();
+        assert_eq!(formatted, again);
+    }
+
+    #[test]
+    fn test_push_page() {
+        let source = DrawioDocument::load(Path::new("test-data/lint-problems.drawio")).unwrap();
+        let mut merged = DrawioDocument::new();
+        merged.push_page(&source.pages()[0], "first");
+        merged.push_page(&source.pages()[0], "again");
+        let pages = merged.pages();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].name(), "again");
+        assert_eq!(pages[0].id(), Some("lint-page-1"));
+        assert_eq!(pages[1].id(), Some("lint-page-1-2"));
+        assert_eq!(pages[1].layers().len(), source.pages()[0].layers().len());
+    }
+
+    #[test]
+    fn test_substitute_placeholders() {
+        let mut doc = DrawioDocument::parse(
+            r#"<mxfile><diagram id="p"><mxGraphModel><root>
+            <mxCell id="0"/><mxCell id="1" parent="0"/>
+            <mxCell id="2" value="v{{ version }} of {{product}}, {{unknown}} {{not a name}}" parent="1" vertex="1"/>
+            <UserObject id="3" label="&lt;b&gt;{{product}}&lt;/b&gt;"><mxCell style="html=1;" parent="1" vertex="1"/></UserObject>
+            </root></mxGraphModel></diagram></mxfile>"#
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(doc.placeholders(), BTreeSet::from(["product", "unknown", "version"].map(String::from)));
+
+        let variables = BTreeMap::from([("version", "1.2"), ("product", "A&B")].map(|(k, v)| (k.to_string(), v.to_string())));
+        doc.substitute_placeholders(&variables);
+        let pages = doc.pages();
+        let values: Vec<&str> = pages[0].cells().iter().map(|c| c.value()).collect();
+        assert_eq!(values[2], "v1.2 of A&B, {{unknown}} {{not a name}}");
+        assert_eq!(values[3], "<b>A&amp;B</b>");
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut doc = DrawioDocument::load(Path::new("test-data/cvm-motivation.drawio")).unwrap();
+        let translations = BTreeMap::from([("Hardware".to_string(), "Matériel & Co".to_string())]);
+        doc.translate(&translations);
+        let pages = doc.pages();
+        let cell = pages[0].cells().iter().find(|c| c.id() == Some("RmPfiGbyRmB0sXnVz50T-2")).unwrap();
+        assert_eq!(cell.value(), "Matériel &amp; Co");
+        assert!(pages[0].cells().iter().any(|c| c.value() == "Hypervisor"));
+    }
+
+    #[test]
+    fn test_resolve_relative_references() {
+        let mut doc = DrawioDocument::parse(
+            r#"<mxfile><diagram id="p"><mxGraphModel><root>
+            <mxCell id="0"/><mxCell id="1" parent="0"/>
+            <UserObject id="2" link="other.drawio#page"><mxCell style="shape=image;image=img/logo.svg;" parent="1" vertex="1"/></UserObject>
+            <UserObject id="3" link="https://example.com"><mxCell style="shape=image;image=data:image/png,abc;" parent="1" vertex="1"/></UserObject>
+            <mxCell id="4" style="shape=image;image=/abs/logo.svg;" parent="1" vertex="1"/>
+            <UserObject id="5" link="mailto:a@example.com"><mxCell parent="1" vertex="1"/></UserObject>
+            <UserObject id="6" link="C:/figures/other.drawio"><mxCell parent="1" vertex="1"/></UserObject>
+            </root></mxGraphModel></diagram></mxfile>"#
+                .as_bytes(),
+        )
+        .unwrap();
+        doc.resolve_relative_references(Path::new("/src/figures"));
+        let pages = doc.pages();
+        let cells = pages[0].cells();
+        assert_eq!(cells[2].link(), Some("/src/figures/other.drawio#page"));
+        assert_eq!(cells[2].style(), "shape=image;image=/src/figures/img/logo.svg;");
+        assert_eq!(cells[3].link(), Some("https://example.com"));
+        assert_eq!(cells[3].style(), "shape=image;image=data:image/png,abc;");
+        assert_eq!(cells[4].style(), "shape=image;image=/abs/logo.svg;");
+        assert_eq!(cells[5].link(), Some("mailto:a@example.com"));
+        //absolute on Windows only
+        let windows_path = Path::new("C:/figures/other.drawio");
+        let expected = match windows_path.is_relative() {
+            true => Path::new("/src/figures").join(windows_path),
+            false => windows_path.to_path_buf(),
+        };
+        assert_eq!(cells[6].link(), expected.to_str());
+        assert!(["tel:123", "javascript:void(0)", "data:image/png,abc", "git+ssh://host/repo"].iter().all(|v| has_uri_scheme(v)));
+        assert!(!["C:/figures", "img/logo.svg", "img/logo:2.svg"].iter().any(|v| has_uri_scheme(v)));
+    }
+}
@@ -0,0 +1,424 @@
+//! `diff` subcommand: semantic comparison of two versions of a .drawio file.
+//!
+//! Raw diffs of drawio files are hard to read, especially for compressed files. Instead, we match
+//! pages, layers and cells by their ids and report what changed.
+//! Optionally, both versions are rendered and the changed pixels are highlighted in an overlay image
+
+use clap::Args;
+use image::{Rgba, RgbaImage};
+use snafu::prelude::*;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::diagram::{DrawioDocument, Page};
+use crate::mock::{bounds, flag_value};
+use crate::{exported_page, AppError, DEFAULT_BUILD_ARGS};
+
+#[derive(Args)]
+pub struct DiffArgs {
+    ///Old version of the file
+    old: PathBuf,
+    ///New version of the file
+    new: PathBuf,
+
+    ///Render both versions and write an image highlighting the changed pixels to this path
+    #[arg(long)]
+    visual: Option<PathBuf>,
+
+    ///Path to drawio binary. Defaults to "drawio"
+    #[arg(long, default_value = "drawio")]
+    drawio: String,
+
+    ///Drawio build args used to render both versions. The format is always png
+    #[arg(long, default_value = DEFAULT_BUILD_ARGS)]
+    build_args: String,
+
+    ///Flag passed verbatim to electron/chromium. Repeat for multiple flags
+    #[arg(long, allow_hyphen_values = true)]
+    electron_args: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Change {
+    Added(String),
+    Removed(String),
+    ///old name, new name
+    Renamed(String, String),
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct CellChanges {
+    added: usize,
+    removed: usize,
+    changed: usize,
+}
+
+#[derive(Debug, PartialEq)]
+struct PageDiff {
+    name: String,
+    layers: Vec<Change>,
+    cells: CellChanges,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct DocumentDiff {
+    pages: Vec<Change>,
+    ///Only pages present in both versions, that contain changes
+    page_diffs: Vec<PageDiff>,
+}
+
+/// Pages are matched by id and fall back to the name for files without page ids
+fn page_key<'a>(page: &Page<'a>) -> &'a str {
+    page.id().unwrap_or(page.name())
+}
+
+fn layer_name(name: &str, idx: usize) -> String {
+    if name.trim().is_empty() {
+        format!("<unnamed layer {}>", idx)
+    } else {
+        name.to_string()
+    }
+}
+
+fn diff_pages(old: &Page, new: &Page) -> PageDiff {
+    let mut layers = Vec::new();
+    let old_layers = old.layers();
+    let new_layers = new.layers();
+    for (idx, layer) in old_layers.iter().enumerate() {
+        match new_layers.iter().enumerate().find(|(_, l)| l.id() == layer.id()) {
+            Some((new_idx, new_layer)) if new_layer.value() != layer.value() => layers.push(Change::Renamed(
+                layer_name(layer.value(), idx),
+                layer_name(new_layer.value(), new_idx),
+            )),
+            Some(_) => (),
+            None => layers.push(Change::Removed(layer_name(layer.value(), idx))),
+        }
+    }
+    for (idx, layer) in new_layers.iter().enumerate() {
+        if !old_layers.iter().any(|l| l.id() == layer.id()) {
+            layers.push(Change::Added(layer_name(layer.value(), idx)));
+        }
+    }
+
+    let mut cells = CellChanges::default();
+    for cell in old.cells() {
+        match new.cells().iter().find(|c| c.id() == cell.id()) {
+            Some(new_cell) if new_cell != cell => cells.changed += 1,
+            Some(_) => (),
+            None => cells.removed += 1,
+        }
+    }
+    cells.added = new
+        .cells()
+        .iter()
+        .filter(|c| !old.cells().iter().any(|o| o.id() == c.id()))
+        .count();
+
+    PageDiff {
+        name: new.name().to_string(),
+        layers,
+        cells,
+    }
+}
+
+fn diff_documents(old: &DrawioDocument, new: &DrawioDocument) -> DocumentDiff {
+    let mut diff = DocumentDiff::default();
+    let old_pages = old.pages();
+    let new_pages = new.pages();
+    for page in &old_pages {
+        match new_pages.iter().find(|p| page_key(p) == page_key(page)) {
+            Some(new_page) => {
+                if new_page.name() != page.name() {
+                    diff.pages
+                        .push(Change::Renamed(page.name().to_string(), new_page.name().to_string()));
+                }
+                let page_diff = diff_pages(page, new_page);
+                if !page_diff.layers.is_empty() || page_diff.cells != CellChanges::default() {
+                    diff.page_diffs.push(page_diff);
+                }
+            }
+            None => diff.pages.push(Change::Removed(page.name().to_string())),
+        }
+    }
+    for page in &new_pages {
+        if !old_pages.iter().any(|p| page_key(p) == page_key(page)) {
+            diff.pages.push(Change::Added(page.name().to_string()));
+        }
+    }
+    diff
+}
+
+fn print_change(kind: &str, change: &Change, indent: &str) {
+    match change {
+        Change::Added(name) => println!("{}{} \"{}\" added", indent, kind, name),
+        Change::Removed(name) => println!("{}{} \"{}\" removed", indent, kind, name),
+        Change::Renamed(old, new) => println!("{}{} \"{}\" renamed to \"{}\"", indent, kind, old, new),
+    }
+}
+
+/// Unchanged pixels are blended with white by this factor, so that the changes stand out
+const FADE: f32 = 0.7;
+const HIGHLIGHT: Rgba<u8> = Rgba([230, 0, 0, 255]);
+
+/// Composite `pixel` onto a white background
+fn on_white(pixel: Rgba<u8>) -> [f32; 3] {
+    let alpha = pixel[3] as f32 / 255.0;
+    [0, 1, 2].map(|i| pixel[i] as f32 * alpha + 255.0 * (1.0 - alpha))
+}
+
+/// Pixel of `image` at (`x`,`y`) if the image is placed at `offset`, transparent outside of the image
+fn pixel_at(image: &RgbaImage, offset: (u32, u32), x: u32, y: u32) -> Rgba<u8> {
+    let transparent = Rgba([0, 0, 0, 0]);
+    match (x.checked_sub(offset.0), y.checked_sub(offset.1)) {
+        (Some(x), Some(y)) => image.get_pixel_checked(x, y).copied().unwrap_or(transparent),
+        _ => transparent,
+    }
+}
+
+/// Overlay of `old` and `new`, with the top left corner of `new` at `shift` pixels from the one of `old`.
+/// Changed pixels are tinted, all others faded. Returns the overlay and the number of changed pixels
+fn diff_images(old: &RgbaImage, new: &RgbaImage, shift: (i64, i64)) -> (RgbaImage, usize) {
+    let old_offset = ((-shift.0).max(0) as u32, (-shift.1).max(0) as u32);
+    let new_offset = (shift.0.max(0) as u32, shift.1.max(0) as u32);
+    let width = (old_offset.0 + old.width()).max(new_offset.0 + new.width());
+    let height = (old_offset.1 + old.height()).max(new_offset.1 + new.height());
+    let mut changed = 0;
+    let overlay = RgbaImage::from_fn(width, height, |x, y| {
+        let old_pixel = pixel_at(old, old_offset, x, y);
+        let new_pixel = pixel_at(new, new_offset, x, y);
+        let old_rgb = on_white(old_pixel);
+        let new_rgb = on_white(new_pixel);
+        if old_rgb == new_rgb {
+            return opaque(new_rgb.map(|v| v + (255.0 - v) * FADE));
+        }
+        changed += 1;
+        //keep some of the content visible, so that one can tell what changed
+        let darkest = if new_rgb.iter().sum::<f32>() < old_rgb.iter().sum::<f32>() { new_rgb } else { old_rgb };
+        opaque([0, 1, 2].map(|i| HIGHLIGHT[i] as f32 * 0.6 + darkest[i] * 0.4))
+    });
+    (overlay, changed)
+}
+
+fn opaque(rgb: [f32; 3]) -> Rgba<u8> {
+    Rgba([rgb[0] as u8, rgb[1] as u8, rgb[2] as u8, 255])
+}
+
+/// `build_args` with the format replaced by png, as we can only compare raster images
+fn render_flags(build_args: &str) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut args = build_args.split(' ');
+    while let Some(arg) = args.next() {
+        match arg {
+            "-f" | "--format" => {
+                args.next();
+            }
+            _ => flags.push(arg.to_string()),
+        }
+    }
+    flags.extend(["-f".to_string(), "png".to_string()]);
+    flags
+}
+
+/// Top left corner of the content of the exported page of `document`, in diagram units
+fn content_origin(document: &DrawioDocument, flags: &[String]) -> Option<(f64, f64)> {
+    let pages = document.pages();
+    let page = pages.get(exported_page(flags))?;
+    let layers: Vec<usize> = (0..page.layers().len()).collect();
+    bounds(page, &layers).map(|v| (v.x, v.y))
+}
+
+/// Shift of the new image relative to the old one that puts the page origin of both at the same pixel.
+/// Exports are cropped to the content, so content that grows to the left or top moves everything else
+fn page_alignment(old: &DrawioDocument, new: &DrawioDocument, flags: &[String]) -> Option<(i64, i64)> {
+    let (old_x, old_y) = content_origin(old, flags)?;
+    let (new_x, new_y) = content_origin(new, flags)?;
+    let scale = flag_value(flags, &["-s", "--scale"]).unwrap_or(1.0);
+    Some((((new_x - old_x) * scale).round() as i64, ((new_y - old_y) * scale).round() as i64))
+}
+
+/// Render `input` to `output` with drawio
+fn render(args: &DiffArgs, input: &Path, output: &Path) -> Result<RgbaImage, AppError> {
+    let result = Command::new(&args.drawio)
+        .args(render_flags(&args.build_args))
+        .arg("-o")
+        .arg(output)
+        .arg(input)
+        .args(&args.electron_args)
+        .output()
+        .whatever_context::<String, AppError>(format!("failed to run drawio for {:?}", input))?;
+    if !result.status.success() || !output.exists() {
+        whatever!(
+            "failed to render {:?} : {}",
+            input,
+            String::from_utf8_lossy(&result.stderr)
+        );
+    }
+    Ok(image::open(output)
+        .whatever_context::<String, AppError>(format!("failed to load rendered image {:?}", output))?
+        .into_rgba8())
+}
+
+fn visual_diff(args: &DiffArgs, documents: (&DrawioDocument, &DrawioDocument), overlay_path: &Path) -> Result<(), AppError> {
+    let render_dir = env::temp_dir().join(format!("drawio-builder-diff-{}", std::process::id()));
+    std::fs::create_dir_all(&render_dir)
+        .whatever_context::<String, AppError>(format!("failed to create {:?}", render_dir))?;
+    let images = render(args, &args.old, &render_dir.join("old.png"))
+        .and_then(|old| Ok((old, render(args, &args.new, &render_dir.join("new.png"))?)));
+    let _ = std::fs::remove_dir_all(&render_dir);
+    let (old, new) = images?;
+
+    if old.dimensions() != new.dimensions() {
+        println!(
+            "The rendered size changed from {}x{} to {}x{} pixels",
+            old.width(),
+            old.height(),
+            new.width(),
+            new.height()
+        );
+    }
+    //the content bounds are only an estimate, e.g. they do not include edges. Thus, we keep the top left alignment
+    //if it fits better
+    let (overlay, changed) = [Some((0, 0)), page_alignment(documents.0, documents.1, &render_flags(&args.build_args))]
+        .into_iter()
+        .flatten()
+        .map(|shift| diff_images(&old, &new, shift))
+        .min_by_key(|(_, changed)| *changed)
+        .unwrap();
+    overlay
+        .save(overlay_path)
+        .whatever_context::<String, AppError>(format!("failed to save {:?}", overlay_path))?;
+    println!(
+        "{} changed pixels. Overlay has been written to {:?}",
+        changed, overlay_path
+    );
+    Ok(())
+}
+
+pub fn run(args: &DiffArgs) -> Result<(), AppError> {
+    let load = |path: &PathBuf| {
+        DrawioDocument::load(path).whatever_context::<String, AppError>(format!("failed to parse file {:?}", path))
+    };
+    let (old, new) = (load(&args.old)?, load(&args.new)?);
+    let diff = diff_documents(&old, &new);
+    if let Some(overlay_path) = &args.visual {
+        visual_diff(args, (&old, &new), overlay_path)?;
+    }
+
+    if diff == DocumentDiff::default() {
+        println!("No changes");
+        return Ok(());
+    }
+    for change in &diff.pages {
+        print_change("Page", change, "");
+    }
+    for page in &diff.page_diffs {
+        println!("Page \"{}\":", page.name);
+        for change in &page.layers {
+            print_change("Layer", change, "  ");
+        }
+        let cells = &page.cells;
+        if cells != &CellChanges::default() {
+            println!(
+                "  Cells: {} added, {} removed, {} changed",
+                cells.added, cells.removed, cells.changed
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(xml: &str) -> DrawioDocument {
+        DrawioDocument::parse(xml.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_diff_images() {
+        let black = Rgba([0, 0, 0, 255]);
+        let old = RgbaImage::from_pixel(2, 1, black);
+        let mut new = RgbaImage::from_pixel(2, 2, black);
+        new.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+        let (overlay, changed) = diff_images(&old, &new, (0, 0));
+        assert_eq!(overlay.dimensions(), (2, 2));
+        //(1,0) changed color, (0,1) and (1,1) only exist in the new image
+        assert_eq!(changed, 3);
+        assert_eq!(overlay.get_pixel(0, 0), &Rgba([178, 178, 178, 255]));
+
+        //content added to the left shifts the rest of the image
+        let mut old = RgbaImage::from_pixel(3, 1, Rgba([255, 255, 255, 255]));
+        old.put_pixel(1, 0, black);
+        let mut new = RgbaImage::from_pixel(4, 1, Rgba([255, 255, 255, 255]));
+        new.put_pixel(0, 0, black);
+        new.put_pixel(2, 0, black);
+        assert_eq!(diff_images(&old, &new, (0, 0)).1, 3);
+        let (overlay, changed) = diff_images(&old, &new, (-1, 0));
+        assert_eq!(overlay.dimensions(), (4, 1));
+        assert_eq!(changed, 1);
+    }
+
+    #[test]
+    fn test_render_flags() {
+        assert_eq!(render_flags("-x -f svg -s 2"), ["-x", "-s", "2", "-f", "png"]);
+        assert_eq!(render_flags("-x"), ["-x", "-f", "png"]);
+    }
+
+    #[test]
+    fn test_diff_documents() {
+        let old = parse(
+            r#"<mxfile>
+                <diagram name="Intro" id="p1"><mxGraphModel><root>
+                    <mxCell id="0"/>
+                    <mxCell id="1" value="base" parent="0"/>
+                    <mxCell id="2" value="details" parent="0"/>
+                    <mxCell id="a" value="kept" parent="1" vertex="1"/>
+                    <mxCell id="b" value="before" parent="1" vertex="1"/>
+                    <mxCell id="c" value="removed" parent="2" vertex="1"/>
+                </root></mxGraphModel></diagram>
+                <diagram name="Old" id="p2"><mxGraphModel><root><mxCell id="0"/></root></mxGraphModel></diagram>
+            </mxfile>"#,
+        );
+        let new = parse(
+            r#"<mxfile>
+                <diagram name="Introduction" id="p1"><mxGraphModel><root>
+                    <mxCell id="0"/>
+                    <mxCell id="1" value="background" parent="0"/>
+                    <mxCell id="3" value="extra" parent="0"/>
+                    <mxCell id="a" value="kept" parent="1" vertex="1"/>
+                    <mxCell id="b" value="after" parent="1" vertex="1"/>
+                    <mxCell id="d" value="added" parent="3" vertex="1"/>
+                </root></mxGraphModel></diagram>
+                <diagram name="New" id="p3"><mxGraphModel><root><mxCell id="0"/></root></mxGraphModel></diagram>
+            </mxfile>"#,
+        );
+        let diff = diff_documents(&old, &new);
+        assert_eq!(
+            diff.pages,
+            vec![
+                Change::Renamed("Intro".to_string(), "Introduction".to_string()),
+                Change::Removed("Old".to_string()),
+                Change::Added("New".to_string()),
+            ]
+        );
+        assert_eq!(
+            diff.page_diffs,
+            vec![PageDiff {
+                name: "Introduction".to_string(),
+                layers: vec![
+                    Change::Renamed("base".to_string(), "background".to_string()),
+                    Change::Removed("details".to_string()),
+                    Change::Added("extra".to_string()),
+                ],
+                //layers count as cells as well
+                cells: CellChanges {
+                    added: 2,
+                    removed: 2,
+                    changed: 2,
+                },
+            }]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+//! Recursive input discovery that optionally skips files excluded by `.gitignore` or `.ignore` files,
+//! e.g. backup copies, editor temp files or vendored example diagrams
+
+use ignore::WalkBuilder;
+use snafu::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::AppError;
+
+/// All .drawio files in the folder `input`. Sub folders are only searched if `recursive` is set.
+/// If `respect_ignore_files` is set, files excluded by `.gitignore`, `.ignore` or git's exclude files are skipped
+pub fn walk_drawio_files(input: &Path, recursive: bool, respect_ignore_files: bool) -> Result<Vec<PathBuf>, AppError> {
+    if input.is_file() {
+        return Ok(vec![input.to_path_buf()]);
+    }
+    let walker = WalkBuilder::new(input)
+        .max_depth(if recursive { None } else { Some(1) })
+        .standard_filters(respect_ignore_files)
+        //we only want to honor ignore files, hidden files are exported as before
+        .hidden(false)
+        //figures are often built outside of a git checkout, e.g. from an unpacked archive
+        .require_git(false)
+        //unlike other hidden folders, git's own folder never holds figures of the project
+        .filter_entry(|v| v.file_name() != ".git")
+        .build();
+    let mut drawio_files = Vec::new();
+    for entry in walker {
+        let entry = entry.whatever_context::<String, AppError>(format!("error listing files in folder {:?}", input))?;
+        let path = entry.path();
+        if path.is_file() && path.extension().is_some_and(|v| v == "drawio") {
+            drawio_files.push(path.to_path_buf());
+        }
+    }
+    drawio_files.sort();
+    Ok(drawio_files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_walk_drawio_files() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-discover-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        for name in ["a.drawio", "backup-a.drawio", "sub/b.drawio", ".git/c.drawio", "notes.txt"] {
+            fs::write(dir.join(name), "<mxfile/>").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "backup-*\n").unwrap();
+        let names = |recursive, respect_ignore_files| -> Vec<String> {
+            walk_drawio_files(&dir, recursive, respect_ignore_files)
+                .unwrap()
+                .iter()
+                .map(|v| v.strip_prefix(&dir).unwrap().display().to_string())
+                .collect()
+        };
+
+        assert_eq!(names(false, false), ["a.drawio", "backup-a.drawio"]);
+        assert_eq!(names(true, false), ["a.drawio", "backup-a.drawio", "sub/b.drawio"]);
+        assert_eq!(names(true, true), ["a.drawio", "sub/b.drawio"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
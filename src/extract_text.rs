@@ -0,0 +1,104 @@
+//! `extract-text` subcommand: dump the text labels of diagrams, e.g. for full-text search
+
+use clap::Args;
+use serde::Serialize;
+use snafu::prelude::*;
+use std::path::PathBuf;
+
+use crate::diagram::DrawioDocument;
+use crate::{find_drawio_files, AppError};
+
+#[derive(Args)]
+pub struct ExtractTextArgs {
+    ///Path to a .drawio file or a folder with .drawio files
+    #[arg(short, long, default_value = "./")]
+    input: String,
+
+    ///Print JSON instead of human readable text
+    #[arg(long, default_value = "false")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct FileText {
+    file: PathBuf,
+    pages: Vec<PageText>,
+}
+
+#[derive(Serialize)]
+struct PageText {
+    name: String,
+    layers: Vec<LayerText>,
+}
+
+#[derive(Serialize)]
+struct LayerText {
+    index: usize,
+    name: String,
+    labels: Vec<String>,
+}
+
+fn extract(path: PathBuf) -> Result<FileText, AppError> {
+    let document = DrawioDocument::load(&path)
+        .whatever_context::<String, AppError>(format!("failed to parse file {:?}", &path))?;
+    let pages = document
+        .pages()
+        .iter()
+        .map(|page| PageText {
+            name: page.name().to_string(),
+            layers: page
+                .layers()
+                .iter()
+                .enumerate()
+                .map(|(index, layer)| LayerText {
+                    index,
+                    name: layer.value().to_string(),
+                    labels: page.text_labels(&[index]),
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(FileText { file: path, pages })
+}
+
+pub fn run(args: &ExtractTextArgs) -> Result<(), AppError> {
+    let files = find_drawio_files(&args.input)?
+        .into_iter()
+        .map(extract)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&files)
+            .whatever_context::<&str, AppError>("failed to serialize text labels")?;
+        println!("{}", json);
+        return Ok(());
+    }
+    for file in files {
+        println!("{}", file.file.display());
+        for page in file.pages {
+            println!("  {}", page.name);
+            for layer in page.layers {
+                println!("    [{}] {}", layer.index, layer.name);
+                for label in layer.labels {
+                    println!("      {}", label.replace('\n', " "));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let text = extract(PathBuf::from("test-data/nonce-truncation.drawio")).unwrap();
+        let layers = &text.pages[0].layers;
+        let names: Vec<&str> = layers.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["base", "estimate", "step 3", "loop", "attack"]);
+        //the label contains a <br> with attributes
+        assert_eq!(layers[4].labels, ["Leak via\nSingle-Stepping / StumbleStepping"]);
+    }
+}
@@ -0,0 +1,101 @@
+//! `fmt` subcommand: rewrite .drawio files in a canonical form, so that saving a file in drawio
+//! without changing the diagram does not show up in version control
+
+use clap::Args;
+use snafu::prelude::*;
+use std::fs;
+
+use crate::diagram::DrawioDocument;
+use crate::{find_drawio_files, AppError};
+
+#[derive(Args)]
+pub struct FmtArgs {
+    ///Path to a .drawio file or a folder with .drawio files
+    #[arg(short, long, default_value = "./")]
+    input: String,
+
+    ///Do not write anything, only fail if there are files that are not formatted, e.g. in CI
+    #[arg(long, default_value = "false")]
+    check: bool,
+}
+
+pub fn run(args: &FmtArgs) -> Result<(), AppError> {
+    let mut unformatted_files = Vec::new();
+    for file in find_drawio_files(&args.input)? {
+        let original =
+            fs::read(&file).whatever_context::<String, AppError>(format!("failed to read {:?}", &file))?;
+        let mut document = DrawioDocument::parse(original.as_slice())
+            .whatever_context::<String, AppError>(format!("failed to parse file {:?}", &file))?;
+        //formatted files are always stored uncompressed, otherwise there is nothing to format
+        document.disable_compression();
+        document.normalize();
+        let mut formatted = Vec::new();
+        document
+            .write(&mut formatted)
+            .whatever_context::<String, AppError>(format!("failed to format {:?}", &file))?;
+        if formatted == original {
+            continue;
+        }
+
+        unformatted_files.push(file.display().to_string());
+        if !args.check {
+            fs::write(&file, formatted).whatever_context::<String, AppError>(format!("failed to write {:?}", &file))?;
+            println!("formatted {}", file.display());
+        }
+    }
+
+    if args.check && !unformatted_files.is_empty() {
+        whatever!(
+            "The following files are not formatted. Run drawio-builder fmt : {}",
+            unformatted_files.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: FmtArgs,
+    }
+
+    fn fmt(args: &[&str]) -> Result<(), AppError> {
+        run(&Cli::parse_from(["fmt"].iter().chain(args)).args)
+    }
+
+    #[test]
+    fn test_fmt() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-fmt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("fig.drawio");
+        fs::write(
+            &file,
+            r#"<mxfile host="Electron" modified="2024-01-01T00:00:00.000Z" agent="Mozilla" version="24.7.17" type="device">
+  <diagram name="Page-1" id="p">
+    <mxGraphModel dx="1434" dy="780" grid="1"><root><mxCell id="0"/><mxCell parent="0" id="1"/></root></mxGraphModel>
+  </diagram>
+</mxfile>"#,
+        )
+        .unwrap();
+        let path = file.to_str().unwrap();
+        let original = fs::read(&file).unwrap();
+        assert!(fmt(&["-i", path, "--check"]).is_err());
+        assert_eq!(fs::read(&file).unwrap(), original, "--check must not write anything");
+
+        fmt(&["-i", path]).unwrap();
+        let formatted = fs::read_to_string(&file).unwrap();
+        //the storage type is not metadata of the save
+        assert!(formatted.contains(r#"type="device""#), "{}", formatted);
+        for volatile in ["host=", "modified=", "agent=", "version=", "dx=", "dy="] {
+            assert!(!formatted.contains(volatile), "{}", formatted);
+        }
+        assert!(formatted.contains(r#"<mxCell id="1" parent="0""#), "{}", formatted);
+        fmt(&["-i", path, "--check"]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
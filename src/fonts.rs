@@ -0,0 +1,112 @@
+//! `fonts` subcommand: report the fonts used by the diagrams and warn about fonts that are not installed.
+//!
+//! Drawio silently falls back to a substitute font during the export, which is a common cause of
+//! figures looking different between machines
+
+use clap::Args;
+use snafu::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::diagram::{DrawioDocument, DEFAULT_FONT_FAMILY};
+use crate::{find_drawio_files, AppError};
+
+#[derive(Args)]
+pub struct FontsArgs {
+    ///Path to a .drawio file or a folder with .drawio files
+    #[arg(short, long, default_value = "./")]
+    input: String,
+}
+
+/// CSS generic families always resolve to some installed font
+const GENERIC_FAMILIES: [&str; 6] = ["serif", "sans-serif", "monospace", "cursive", "fantasy", "system-ui"];
+
+/// Metric-compatible substitutes of drawio's default font. Helvetica itself is rarely installed under that name
+/// outside of macOS, but fontconfig maps it to one of these without changing the layout of the text
+const DEFAULT_FONT_SUBSTITUTES: [&str; 5] = ["Liberation Sans", "Nimbus Sans", "Nimbus Sans L", "TeX Gyre Heros", "Arial"];
+
+/// Maps each font family to the files using it. Web fonts, i.e. fonts with a `fontSource`, are skipped
+/// as drawio downloads them during the export
+fn collect_fonts(files: &[PathBuf]) -> Result<BTreeMap<String, BTreeSet<PathBuf>>, AppError> {
+    let mut fonts: BTreeMap<String, BTreeSet<PathBuf>> = BTreeMap::new();
+    for file in files {
+        let document = DrawioDocument::load(file)
+            .whatever_context::<String, AppError>(format!("failed to parse file {:?}", file))?;
+        for page in document.pages() {
+            //the value of layers is their name, which is not rendered
+            for cell in page.cells().iter().filter(|c| page.layer_index(c).is_some()) {
+                let web_font = cell.style_value("fontSource").and(cell.style_value("fontFamily"));
+                for family in cell.font_families() {
+                    if Some(family.as_str()) == web_font || GENERIC_FAMILIES.contains(&family.to_ascii_lowercase().as_str()) {
+                        continue;
+                    }
+                    fonts.entry(family).or_default().insert(file.clone());
+                }
+            }
+        }
+    }
+    Ok(fonts)
+}
+
+pub fn run(args: &FontsArgs) -> Result<(), AppError> {
+    let files = find_drawio_files(&args.input)?;
+    let fonts = collect_fonts(&files)?;
+
+    let mut db = fontdb::Database::new();
+    db.load_system_fonts();
+    let is_installed = |family: &str| {
+        db.faces()
+            .any(|face| face.families.iter().any(|(name, _)| name.eq_ignore_ascii_case(family)))
+    };
+    //name of the installed font that is used for `family`, if any
+    let installed_as = |family: &str| -> Option<String> {
+        if is_installed(family) {
+            return Some("yes".to_string());
+        }
+        if !family.eq_ignore_ascii_case(DEFAULT_FONT_FAMILY) {
+            return None;
+        }
+        DEFAULT_FONT_SUBSTITUTES.iter().find(|v| is_installed(v)).map(|v| format!("as {}", v))
+    };
+
+    let width = fonts.keys().map(|v| v.len()).max().unwrap_or_default().max("Font".len());
+    println!("{:width$}  Installed  Figures", "Font");
+    let mut missing = Vec::new();
+    for (family, users) in &fonts {
+        let installed = installed_as(family);
+        println!("{:width$}  {:9}  {}", family, installed.as_deref().unwrap_or("no"), users.len());
+        if installed.is_none() {
+            missing.push((family, users));
+        }
+    }
+    for (family, users) in missing {
+        let users: Vec<_> = users.iter().map(|v| v.display().to_string()).collect();
+        eprintln!(
+            "warning: font \"{}\" is not installed. These figures will use a substitute font: {}",
+            family,
+            users.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collect_fonts_skips_layers() {
+        let path = std::env::temp_dir().join(format!("drawio-builder-fonts-{}.drawio", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"<mxfile><diagram id="p" name="Page-1"><mxGraphModel><root>
+            <mxCell id="0"/><mxCell id="1" value="Background" parent="0"/>
+            <mxCell id="2" value="code" style="fontFamily=Courier New;" vertex="1" parent="1"/>
+            </root></mxGraphModel></diagram></mxfile>"#,
+        )
+        .unwrap();
+        let fonts = collect_fonts(std::slice::from_ref(&path)).unwrap();
+        assert_eq!(fonts.keys().collect::<Vec<_>>(), ["Courier New"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
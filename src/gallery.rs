@@ -0,0 +1,82 @@
+//! Static html page that shows all exported figures with their alt text, e.g. to review the alt texts
+//! before copying figures and alt texts to a website
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use std::path::PathBuf;
+
+/// File name of the gallery in the output folder
+pub const GALLERY_FILE_NAME: &str = "index.html";
+
+const SRC_ESCAPE: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'%').add(b'&').add(b'#').add(b'?').add(b'<').add(b'>');
+
+/// Exported images of a figure, which share the gallery section `title`
+pub struct GalleryFigure {
+    pub title: String,
+    ///Path and alt text of each export step
+    pub images: Vec<(PathBuf, String)>,
+}
+
+/// Escape `text` for use in html text and attribute values
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Html page with one section per figure. Images are linked by their file name, i.e. the page has to be placed next to them
+pub fn render(figures: &[GalleryFigure]) -> String {
+    let mut html = String::from(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Figures</title>
+<style>
+figure { display: inline-block; vertical-align: top; max-width: 40em; margin: 1em; }
+img { max-width: 100%; }
+.alt { white-space: pre-line; color: #555; }
+</style>
+</head>
+<body>
+"#,
+    );
+    for figure in figures {
+        html += &format!("<section>\n<h2>{}</h2>\n", escape(&figure.title));
+        for (image, alt_text) in &figure.images {
+            let name = image.file_name().unwrap_or_default().to_string_lossy();
+            html += &format!(
+                "<figure><img src=\"{}\" alt=\"{}\"><figcaption>{}<div class=\"alt\">{}</div></figcaption></figure>\n",
+                utf8_percent_encode(&name, SRC_ESCAPE),
+                escape(alt_text),
+                escape(&name),
+                escape(alt_text)
+            );
+        }
+        html += "</section>\n";
+    }
+    html += "</body>\n</html>\n";
+    html
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let figures = [GalleryFigure {
+            title: "\"arch & design.drawio\" page \"<Overview>\"".to_string(),
+            images: vec![
+                (PathBuf::from("out/arch & design-0.png"), "Client\nServer".to_string()),
+                (PathBuf::from("out/arch & design-1.png"), "Say \"hi\"".to_string()),
+            ],
+        }];
+        let html = render(&figures);
+        assert!(html.contains("<h2>&quot;arch &amp; design.drawio&quot; page &quot;&lt;Overview&gt;&quot;</h2>"));
+        assert!(html.contains(r#"<img src="arch%20%26%20design-0.png" alt="Client
+Server">"#));
+        assert!(html.contains(r#"<img src="arch%20%26%20design-1.png" alt="Say &quot;hi&quot;">"#));
+        assert!(html.contains("<figcaption>arch &amp; design-1.png<div"));
+    }
+}
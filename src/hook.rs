@@ -0,0 +1,213 @@
+//! `hook` subcommand: git hooks that keep committed figures in sync with their .drawio sources
+
+use clap::{Args, Subcommand};
+use snafu::prelude::*;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::deps::Dependencies;
+use crate::state::{self, BuildState};
+use crate::{
+    alt_text_path, build, drawio_flags, input_files, layer_steps, load_config, load_translations, output_path, plan_build,
+    plan_export_steps, state_key, AppError, BuildArgs,
+};
+
+#[derive(Args)]
+pub struct HookArgs {
+    #[command(subcommand)]
+    hook: Hook,
+}
+
+#[derive(Subcommand)]
+enum Hook {
+    ///Rebuild the figures of all staged .drawio files and stage the results
+    PreCommit(PreCommitArgs),
+}
+
+#[derive(Args)]
+struct PreCommitArgs {
+    #[command(flatten)]
+    build: BuildArgs,
+
+    ///Do not build anything, only fail if the figures of staged .drawio files are outdated
+    #[arg(long, default_value = "false")]
+    check: bool,
+}
+
+/// Run git in the folder `repo`
+fn git<S: AsRef<OsStr>>(repo: &Path, args: &[S]) -> Result<String, AppError> {
+    let command = args.iter().map(|v| v.as_ref().to_string_lossy()).collect::<Vec<_>>().join(" ");
+    let output = Command::new("git")
+        .current_dir(repo)
+        .args(args)
+        .output()
+        .whatever_context::<String, AppError>(format!("failed to run git {}", command))?;
+    if !output.status.success() {
+        whatever!("git {} failed : {}", command, String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Input files of the build that are staged for the next commit or depend on a staged file,
+/// e.g. link to a staged .drawio file or embed a staged image. `repo` is a folder of the git repository
+fn staged_inputs(args: &BuildArgs, repo: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let repo_root = PathBuf::from(git(repo, &["rev-parse", "--show-toplevel"])?.trim());
+    let staged: Vec<PathBuf> = git(repo, &["diff", "--cached", "--name-only", "--diff-filter=ACMR", "-z"])?
+        .split('\0')
+        .filter(|v| !v.is_empty())
+        .filter_map(|v| repo_root.join(v).canonicalize().ok())
+        .collect();
+    let inputs = input_files(&args.input, args.recursive, args.respect_ignore_files)?;
+    let dependencies = Dependencies::resolve(&inputs);
+    Ok(inputs
+        .into_iter()
+        .filter(|file| {
+            dependencies
+                .sources(file)
+                .iter()
+                .any(|v| v.canonicalize().is_ok_and(|v| staged.contains(&v)))
+        })
+        .collect())
+}
+
+/// Fail if any figure of `files` is missing or older than its source
+fn check(args: &BuildArgs, files: Vec<PathBuf>) -> Result<(), AppError> {
+    let config = load_config(args.config.as_deref())?;
+    let state = BuildState::load(Path::new(&args.output));
+    let mut outdated = Vec::new();
+    let translations = load_translations(args.locales.as_deref())?;
+    let dependencies = Dependencies::resolve(&files);
+    let planned = plan_build(files, &config, &drawio_flags(args)?, args.all_pages, &translations, &mut |v| {
+        eprintln!("Warning: {}", v)
+    })?;
+    for (file, build_config) in planned {
+        let sources = dependencies.sources(&file);
+        let input_hash = state::hash_files(&sources).whatever_context::<String, AppError>(format!("failed to read {:?}", &sources))?;
+        let freshness = state.freshness(
+            &state_key(&file, &build_config),
+            &state::fingerprint(&build_config, args.backend),
+            &sources,
+            &input_hash,
+        );
+        let steps = plan_export_steps(&file, &sources, &build_config, &args.output, &freshness);
+        outdated.extend(steps.into_iter().map(|v| v.output_path.display().to_string()));
+    }
+    if !outdated.is_empty() {
+        whatever!(
+            "The following figures are outdated. Run drawio-builder and stage them : {}",
+            outdated.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn pre_commit(args: &PreCommitArgs, repo: &Path) -> Result<(), AppError> {
+    let files = staged_inputs(&args.build, repo)?;
+    if files.is_empty() {
+        return Ok(());
+    }
+    if args.check {
+        return check(&args.build, files);
+    }
+
+    let built = build(&args.build, files)?;
+    let mut outputs = Vec::new();
+    for (file, build_config) in &built {
+        for idx in 0..layer_steps(&build_config.layer_config).len() {
+            let output = output_path(file, build_config, &args.build.output, idx);
+            if args.build.alt_text {
+                outputs.push(alt_text_path(&output));
+            }
+            outputs.push(output);
+        }
+    }
+    let mut git_args = vec![OsStr::new("add"), OsStr::new("--")];
+    git_args.extend(outputs.iter().map(|v| v.as_os_str()));
+    git(repo, &git_args)?;
+    Ok(())
+}
+
+pub fn run(args: &HookArgs) -> Result<(), AppError> {
+    match &args.hook {
+        Hook::PreCommit(pre_commit_args) => pre_commit(pre_commit_args, Path::new(".")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DrawioConfig, Translations};
+    use clap::Parser;
+    use std::fs;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: PreCommitArgs,
+    }
+
+    fn page(link: &str) -> String {
+        format!(
+            r#"<mxfile><diagram id="p" name="Page-1"><mxGraphModel><root><mxCell id="0"/><mxCell id="1" parent="0"/>
+            <UserObject id="2" label="see" link="{}"><mxCell style="text;" vertex="1" parent="1"/></UserObject>
+            </root></mxGraphModel></diagram></mxfile>"#,
+            link
+        )
+    }
+
+    /// Git repository with a.drawio, b.drawio linking to a.drawio and the unrelated c.drawio, of which only a.drawio is staged
+    fn repo(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-hook-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "-q"]).unwrap();
+        fs::write(dir.join("a.drawio"), page("https://example.com")).unwrap();
+        fs::write(dir.join("b.drawio"), page("a.drawio")).unwrap();
+        fs::write(dir.join("c.drawio"), page("https://example.com")).unwrap();
+        git(&dir, &["add", "a.drawio"]).unwrap();
+        dir
+    }
+
+    fn args(dir: &Path, check: bool) -> PreCommitArgs {
+        let out = dir.join("out");
+        let mut cli = vec!["pre-commit", "-i", dir.to_str().unwrap(), "-o", out.to_str().unwrap()];
+        if check {
+            cli.push("--check");
+        }
+        Cli::parse_from(cli).args
+    }
+
+    #[test]
+    fn test_staged_inputs() {
+        let dir = repo("staged");
+        let files = staged_inputs(&args(&dir, false).build, &dir).unwrap();
+        let names: Vec<_> = files.iter().map(|v| v.file_name().unwrap()).collect();
+        assert_eq!(names, ["a.drawio", "b.drawio"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = repo("check");
+        let args = args(&dir, true);
+        let err = pre_commit(&args, &dir).unwrap_err().to_string();
+        assert!(err.contains("a-0.png") && err.contains("b-0.png"), "{}", err);
+        assert!(!err.contains("c-0.png"), "{}", err);
+
+        let files = staged_inputs(&args.build, &dir).unwrap();
+        let flags = drawio_flags(&args.build).unwrap();
+        let planned = plan_build(files, &DrawioConfig::default(), &flags, false, &Translations::new(), &mut |_| ()).unwrap();
+        fs::create_dir_all(dir.join("out")).unwrap();
+        for (file, build_config) in &planned {
+            for idx in 0..layer_steps(&build_config.layer_config).len() {
+                fs::write(output_path(file, build_config, &args.build.output, idx), "").unwrap();
+            }
+        }
+        pre_commit(&args, &dir).unwrap();
+        //nothing to check without staged .drawio files
+        git(&dir, &["reset", "-q"]).unwrap();
+        fs::remove_dir_all(dir.join("out")).unwrap();
+        pre_commit(&args, &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
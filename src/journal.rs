@@ -0,0 +1,196 @@
+//! Journal of export steps, written while the build runs. If a build is interrupted (Ctrl-C, OOM kill,
+//! CI timeout), outputs may be half written but still newer than their sources. Each step is recorded when its
+//! export starts and, with the hash of its output, when it completed. With `--resume`, completed steps whose output
+//! still has the recorded hash are skipped and started but not completed steps are rebuilt. Steps the interrupted
+//! build did not touch are checked as usual
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::state::hash_files;
+
+const JOURNAL_FILE_NAME: &str = "drawio-builder-journal.jsonl";
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JournalEntry {
+    Started {
+        output: PathBuf,
+    },
+    Completed {
+        output: PathBuf,
+        output_hash: String,
+        ///Hash of the sources and fingerprint of the settings the output was built from
+        input_hash: String,
+        fingerprint: String,
+    },
+}
+
+/// What the interrupted build did with an output
+#[derive(Debug, PartialEq)]
+pub enum JournalStatus {
+    ///Not touched, the usual freshness checks apply
+    Unknown,
+    ///Started, but not completed, or changed since. The output may be half written
+    Interrupted,
+    ///Completed from the same sources and settings and unchanged since
+    Completed,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    ///Last entry of each output in the interrupted build. Empty unless we resume
+    previous: HashMap<PathBuf, JournalEntry>,
+    resuming: bool,
+}
+
+impl Journal {
+    /// Start a new journal in `out_dir`. If `resume` is set and there is a journal of an interrupted build,
+    /// its entries are kept and new entries are appended
+    pub fn open(out_dir: &Path, resume: bool) -> std::io::Result<Self> {
+        let path = out_dir.join(JOURNAL_FILE_NAME);
+        let resuming = resume && path.exists();
+        let mut previous = HashMap::new();
+        if resuming {
+            //the last line may be incomplete if the build was killed while writing it
+            for line in BufReader::new(File::open(&path)?).lines() {
+                if let Ok(entry) = serde_json::from_str::<JournalEntry>(&line?) {
+                    let (JournalEntry::Started { output } | JournalEntry::Completed { output, .. }) = &entry;
+                    previous.insert(output.clone(), entry);
+                }
+            }
+        }
+        let file = File::options()
+            .create(true)
+            .write(true)
+            .append(resuming)
+            .truncate(!resuming)
+            .open(&path)?;
+        Ok(Journal {
+            path,
+            file: Mutex::new(file),
+            previous,
+            resuming,
+        })
+    }
+
+    /// True if we continue an interrupted build
+    pub fn resuming(&self) -> bool {
+        self.resuming
+    }
+
+    /// What the interrupted build did with `output`, which is now built from sources with `input_hash`
+    /// and settings with `fingerprint`
+    pub fn status(&self, output: &Path, input_hash: &str, fingerprint: &str) -> JournalStatus {
+        match self.previous.get(output) {
+            None => JournalStatus::Unknown,
+            Some(JournalEntry::Completed {
+                output_hash,
+                input_hash: completed_input_hash,
+                fingerprint: completed_fingerprint,
+                ..
+            }) if completed_input_hash == input_hash
+                && completed_fingerprint == fingerprint
+                && hash_files(&[output.to_path_buf()]).is_ok_and(|v| v == *output_hash) =>
+            {
+                JournalStatus::Completed
+            }
+            Some(_) => JournalStatus::Interrupted,
+        }
+    }
+
+    /// Entries of one file, whose steps share the hash of the sources and the fingerprint of the settings
+    pub fn file<'a>(&'a self, input_hash: &'a str, fingerprint: &'a str) -> FileJournal<'a> {
+        FileJournal {
+            journal: self,
+            input_hash,
+            fingerprint,
+        }
+    }
+
+    /// Entries are written right away, so that they survive the build being killed. This additionally
+    /// makes sure that they survive a crash of the machine
+    pub fn sync(&self) -> std::io::Result<()> {
+        self.file.lock().unwrap().sync_data()
+    }
+
+    fn append(&self, entry: &JournalEntry) -> std::io::Result<()> {
+        let line = serde_json::to_string(entry)? + "\n";
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+
+    /// Remove the journal after a complete build, so that a later `--resume` does not trust outdated entries
+    pub fn remove(self) -> std::io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+}
+
+pub struct FileJournal<'a> {
+    journal: &'a Journal,
+    input_hash: &'a str,
+    fingerprint: &'a str,
+}
+
+impl FileJournal<'_> {
+    /// Record that the export of `output` starts, i.e. that it may be half written until it is completed
+    pub fn started(&self, output: &Path) -> std::io::Result<()> {
+        self.journal.append(&JournalEntry::Started {
+            output: output.to_path_buf(),
+        })
+    }
+
+    /// Record that `output` is complete
+    pub fn completed(&self, output: &Path) -> std::io::Result<()> {
+        self.journal.append(&JournalEntry::Completed {
+            output: output.to_path_buf(),
+            output_hash: hash_files(&[output.to_path_buf()])?,
+            input_hash: self.input_hash.to_string(),
+            fingerprint: self.fingerprint.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resume() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let [complete, changed, interrupted, untouched] =
+            ["fig-0.png", "fig-1.png", "fig-2.png", "fig-3.png"].map(|v| dir.join(v));
+        for output in [&complete, &changed, &interrupted, &untouched] {
+            fs::write(output, "png").unwrap();
+        }
+
+        let journal = Journal::open(&dir, true).unwrap();
+        //without an earlier journal, there is nothing to resume
+        assert!(!journal.resuming());
+        let file_journal = journal.file("in", "f");
+        for output in [&complete, &changed, &interrupted] {
+            file_journal.started(output).unwrap();
+        }
+        file_journal.completed(&complete).unwrap();
+        file_journal.completed(&changed).unwrap();
+        drop(journal);
+        fs::write(&changed, "pn").unwrap();
+
+        let journal = Journal::open(&dir, true).unwrap();
+        assert!(journal.resuming());
+        assert_eq!(journal.status(&complete, "in", "f"), JournalStatus::Completed);
+        assert_eq!(journal.status(&complete, "in", "g"), JournalStatus::Interrupted);
+        assert_eq!(journal.status(&changed, "in", "f"), JournalStatus::Interrupted);
+        assert_eq!(journal.status(&interrupted, "in", "f"), JournalStatus::Interrupted);
+        assert_eq!(journal.status(&untouched, "in", "f"), JournalStatus::Unknown);
+        journal.remove().unwrap();
+        assert!(!Journal::open(&dir, true).unwrap().resuming());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,457 @@
+//! `lint` subcommand: flag common problems in diagrams before they cause surprises during the export
+
+use clap::{Args, ValueEnum};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Deserialize;
+use serde_json::json;
+use snafu::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagram::{DrawioDocument, Geometry, Page};
+use crate::{find_drawio_files, load_config, AppError};
+
+#[derive(Args)]
+pub struct LintArgs {
+    ///Path to a .drawio file or a folder with .drawio files
+    #[arg(short, long, default_value = "./")]
+    input: String,
+
+    ///Path to optional config file. Use its "lint" section to change rule severities
+    #[arg(long)]
+    config: Option<String>,
+
+    ///Also fail on warnings
+    #[arg(long)]
+    deny: Option<Deny>,
+
+    ///Output format. Use "sarif" to upload the findings to code scanning UIs like GitHub code scanning
+    #[arg(long, default_value = "text")]
+    lint_format: LintFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Deny {
+    Warnings,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LintFormat {
+    Text,
+    Sarif,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    ///Rule is disabled
+    Allow,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Allow => write!(f, "allow"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    ///Layer without any cells
+    EmptyLayer,
+    ///Page without any cells besides the layers
+    EmptyPage,
+    ///Shape far outside of the page. Drawio crops exports to the diagram bounds,
+    /// so these lead to huge, mostly empty images
+    OutsidePage,
+    ///Shape with zero width or height
+    ZeroSize,
+    ///Layer without a name, e.g. the default "Background" layer
+    UnnamedLayer,
+    ///Layer with the same name as an earlier layer on the same page
+    DuplicateLayerName,
+}
+
+const RULES: [Rule; 6] = [
+    Rule::EmptyLayer,
+    Rule::EmptyPage,
+    Rule::OutsidePage,
+    Rule::ZeroSize,
+    Rule::UnnamedLayer,
+    Rule::DuplicateLayerName,
+];
+
+impl Rule {
+    fn name(&self) -> &'static str {
+        match self {
+            Rule::EmptyLayer => "empty-layer",
+            Rule::EmptyPage => "empty-page",
+            Rule::OutsidePage => "outside-page",
+            Rule::ZeroSize => "zero-size",
+            Rule::UnnamedLayer => "unnamed-layer",
+            Rule::DuplicateLayerName => "duplicate-layer-name",
+        }
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Rule::EmptyLayer => "Layer without any content",
+            Rule::EmptyPage => "Page without any content",
+            Rule::OutsidePage => "Shape far outside of the page, which leads to huge, mostly empty exports",
+            Rule::ZeroSize => "Shape with zero width or height",
+            Rule::UnnamedLayer => "Layer without a name",
+            Rule::DuplicateLayerName => "Layer with the same name as another layer on the same page",
+        }
+    }
+}
+
+struct Finding {
+    rule: Rule,
+    severity: Severity,
+    file: PathBuf,
+    page: String,
+    ///Id of the offending cell, if the finding is about a single cell
+    cell: Option<String>,
+    message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {}, page \"{}\"",
+            self.severity,
+            self.rule.name(),
+            self.file.display(),
+            self.page
+        )?;
+        if let Some(cell) = &self.cell {
+            write!(f, ", cell \"{}\"", cell)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Shapes may stick out of the page by this fraction of the page size before they are reported
+const OUTSIDE_PAGE_TOLERANCE: f64 = 0.5;
+
+fn is_far_outside(geometry: &Geometry, page_width: f64, page_height: f64) -> bool {
+    let margin_x = page_width * OUTSIDE_PAGE_TOLERANCE;
+    let margin_y = page_height * OUTSIDE_PAGE_TOLERANCE;
+    geometry.x + geometry.width < -margin_x
+        || geometry.y + geometry.height < -margin_y
+        || geometry.x > page_width + margin_x
+        || geometry.y > page_height + margin_y
+}
+
+/// Run all rules on `page`. Findings have the rule's default severity
+fn lint_page(file: &Path, page: &Page) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut report = |rule: Rule, cell: Option<&str>, message: String| {
+        findings.push(Finding {
+            rule,
+            severity: rule.default_severity(),
+            file: file.to_path_buf(),
+            page: page.name().to_string(),
+            cell: cell.map(str::to_string),
+            message,
+        })
+    };
+
+    let layers = page.layers();
+    //Both make it hard to tell which layer an index in the config refers to
+    for (idx, layer) in layers.iter().enumerate() {
+        let name = layer.value().trim();
+        if name.is_empty() {
+            report(Rule::UnnamedLayer, layer.id(), format!("layer {} has no name", idx));
+        } else if let Some(first) = layers[..idx].iter().position(|l| l.value().trim() == name) {
+            report(
+                Rule::DuplicateLayerName,
+                layer.id(),
+                format!("layer {} has the same name \"{}\" as layer {}", idx, name, first),
+            );
+        }
+    }
+
+    let content: Vec<_> = page
+        .cells()
+        .iter()
+        .filter(|c| page.layer_index(c).is_some())
+        .collect();
+    if content.is_empty() {
+        report(Rule::EmptyPage, None, "page has no content".to_string());
+    } else {
+        for (idx, layer) in layers.iter().enumerate() {
+            if !content.iter().any(|c| page.layer_index(c) == Some(idx)) {
+                report(
+                    Rule::EmptyLayer,
+                    layer.id(),
+                    format!("layer {} \"{}\" is empty", idx, layer.value()),
+                );
+            }
+        }
+    }
+
+    for cell in content.iter().filter(|c| c.is_vertex()) {
+        //edge labels have no size of their own and are positioned relative to their edge
+        let (Some(geometry), Some(absolute)) = (cell.geometry(), page.absolute_geometry(cell)) else {
+            continue;
+        };
+        if geometry.width == 0.0 || geometry.height == 0.0 {
+            report(
+                Rule::ZeroSize,
+                cell.id(),
+                format!("shape has size {}x{}", geometry.width, geometry.height),
+            );
+        }
+        if let Some((width, height)) = page.page_size() {
+            if is_far_outside(&absolute, width, height) {
+                report(
+                    Rule::OutsidePage,
+                    cell.id(),
+                    format!(
+                        "shape at ({}, {}) is far outside of the {}x{} page",
+                        absolute.x, absolute.y, width, height
+                    ),
+                );
+            }
+        }
+    }
+    findings
+}
+
+/// Lint all pages of `file`, applying the configured severities
+fn lint_file(file: &Path, severities: &BTreeMap<String, Severity>) -> Result<Vec<Finding>, AppError> {
+    let document = DrawioDocument::load(file)
+        .whatever_context::<String, AppError>(format!("failed to parse file {:?}", file))?;
+    let mut findings = Vec::new();
+    for page in document.pages() {
+        for mut finding in lint_page(file, &page) {
+            if let Some(severity) = severities.get(finding.rule.name()) {
+                finding.severity = *severity;
+            }
+            if finding.severity != Severity::Allow {
+                findings.push(finding);
+            }
+        }
+    }
+    Ok(findings)
+}
+
+/// Characters that need to be escaped in SARIF's artifact URIs
+const URI_ESCAPE: &AsciiSet = &CONTROLS.add(b' ').add(b'%').add(b'#').add(b'?');
+
+/// 1-based line of the first occurrence of `needle` in `content`. Defaults to the first line,
+/// e.g. for compressed files
+fn find_line(content: &str, needle: &str) -> usize {
+    content
+        .find(needle)
+        .map(|pos| content[..pos].matches('\n').count() + 1)
+        .unwrap_or(1)
+}
+
+/// Root of the git checkout containing `dir`, or `dir` itself outside of a checkout
+fn repo_root(dir: &Path) -> &Path {
+    dir.ancestors().find(|v| v.join(".git").exists()).unwrap_or(dir)
+}
+
+/// `path` as URI reference, with forward slashes and reserved characters escaped
+fn uri_path(path: &Path) -> String {
+    utf8_percent_encode(&path.to_string_lossy().replace('\\', "/"), URI_ESCAPE).to_string()
+}
+
+/// Absolute file URI of `path`, e.g. "file:///C:/figures" on Windows
+fn file_uri(path: &Path) -> String {
+    let path = uri_path(path);
+    match path.starts_with('/') {
+        true => format!("file://{}", path),
+        false => format!("file:///{}", path),
+    }
+}
+
+/// SARIF report of `findings`. Code scanning UIs only map findings to files if their paths are relative to the
+/// repository, so paths are made relative to `root` and `root` is recorded as the "%SRCROOT%" base
+fn to_sarif(findings: &[Finding], root: &Path) -> serde_json::Value {
+    let rules: Vec<_> = RULES
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.name(),
+                "shortDescription": { "text": rule.description() },
+                "defaultConfiguration": { "level": rule.default_severity().to_string() },
+            })
+        })
+        .collect();
+
+    let mut contents: BTreeMap<&Path, String> = BTreeMap::new();
+    let results: Vec<_> = findings
+        .iter()
+        .map(|finding| {
+            let content = contents
+                .entry(&finding.file)
+                .or_insert_with(|| fs::read_to_string(&finding.file).unwrap_or_default());
+            let line = match &finding.cell {
+                //leading whitespace, so that e.g. `grid="1"` does not match the cell with id 1
+                Some(cell) => find_line(content, &format!(" id=\"{}\"", cell)),
+                None => find_line(content, &format!(" name=\"{}\"", finding.page)),
+            };
+            let file = fs::canonicalize(&finding.file).unwrap_or_else(|_| finding.file.clone());
+            let artifact_location = match file.strip_prefix(root) {
+                Ok(relative) => json!({ "uri": uri_path(relative), "uriBaseId": "%SRCROOT%" }),
+                Err(_) => json!({ "uri": file_uri(&file) }),
+            };
+            let logical_name = match &finding.cell {
+                Some(cell) => format!("{}/{}", finding.page, cell),
+                None => finding.page.clone(),
+            };
+            json!({
+                "ruleId": finding.rule.name(),
+                "level": finding.severity.to_string(),
+                "message": { "text": finding.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": artifact_location,
+                        "region": { "startLine": line },
+                    },
+                    "logicalLocations": [{ "name": logical_name }],
+                }],
+            })
+        })
+        .collect();
+
+    json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "drawio-builder",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "originalUriBaseIds": {
+                "%SRCROOT%": { "uri": format!("{}/", file_uri(root).trim_end_matches('/')) },
+            },
+            "results": results,
+        }],
+    })
+}
+
+pub fn run(args: &LintArgs) -> Result<(), AppError> {
+    let config = load_config(args.config.as_deref())?;
+    let severities = config.lint.unwrap_or_default();
+    for name in severities.keys() {
+        if !RULES.iter().any(|r| r.name() == name) {
+            whatever!("Unknown lint rule \"{}\" in config file", name);
+        }
+    }
+
+    let mut findings = Vec::new();
+    for file in find_drawio_files(&args.input)? {
+        findings.extend(lint_file(&file, &severities)?);
+    }
+    match args.lint_format {
+        LintFormat::Text => {
+            for finding in &findings {
+                println!("{}", finding);
+            }
+        }
+        LintFormat::Sarif => {
+            let dir = fs::canonicalize(".").whatever_context::<&str, AppError>("failed to resolve working directory")?;
+            let sarif = serde_json::to_string_pretty(&to_sarif(&findings, repo_root(&dir)))
+                .whatever_context::<&str, AppError>("failed to serialize SARIF report")?;
+            println!("{}", sarif);
+        }
+    }
+
+    let fail_at = match args.deny {
+        Some(Deny::Warnings) => Severity::Warning,
+        None => Severity::Error,
+    };
+    let failed = findings.iter().filter(|f| f.severity >= fail_at).count();
+    if failed > 0 {
+        whatever!("Lint failed with {} finding(s)", failed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_far_outside() {
+        let shape = |x, y| Geometry {
+            x,
+            y,
+            width: 10.0,
+            height: 10.0,
+        };
+        assert!(!is_far_outside(&shape(50.0, 50.0), 100.0, 100.0));
+        assert!(!is_far_outside(&shape(-30.0, 120.0), 100.0, 100.0));
+        assert!(is_far_outside(&shape(-100.0, 50.0), 100.0, 100.0));
+        assert!(is_far_outside(&shape(50.0, 200.0), 100.0, 100.0));
+    }
+
+    #[test]
+    fn test_lint_findings() {
+        let findings = lint_file(Path::new("test-data/lint-problems.drawio"), &BTreeMap::new()).unwrap();
+        let got: Vec<(&str, Option<&str>)> = findings.iter().map(|f| (f.rule.name(), f.cell.as_deref())).collect();
+        assert_eq!(
+            got,
+            vec![
+                ("duplicate-layer-name", Some("3")),
+                ("unnamed-layer", Some("4")),
+                ("empty-layer", Some("2")),
+                ("zero-size", Some("shape-flat")),
+                ("outside-page", Some("shape-far")),
+                ("unnamed-layer", Some("1")),
+                ("empty-page", None),
+            ]
+        );
+
+        let severities = BTreeMap::from([("zero-size".to_string(), Severity::Allow), ("empty-page".to_string(), Severity::Error)]);
+        let findings = lint_file(Path::new("test-data/lint-problems.drawio"), &severities).unwrap();
+        assert!(findings.iter().all(|f| f.rule != Rule::ZeroSize));
+        assert!(findings.iter().any(|f| f.rule == Rule::EmptyPage && f.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_sarif_locations() {
+        let findings = lint_file(Path::new("./test-data/lint-problems.drawio"), &BTreeMap::new()).unwrap();
+        let root = fs::canonicalize(".").unwrap();
+        let sarif = to_sarif(&findings, &root);
+        let results = sarif["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), findings.len());
+        let zero_size = results.iter().find(|r| r["ruleId"] == "zero-size").unwrap();
+        let location = &zero_size["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "test-data/lint-problems.drawio");
+        assert_eq!(location["artifactLocation"]["uriBaseId"], "%SRCROOT%");
+        assert_eq!(location["region"]["startLine"], 13);
+        let base = sarif["runs"][0]["originalUriBaseIds"]["%SRCROOT%"]["uri"].as_str().unwrap();
+        assert_eq!(base, format!("{}/", file_uri(&root)));
+
+        //files outside of the repository keep their absolute path
+        let sarif = to_sarif(&findings, &root.join("test-data/other"));
+        let location = &sarif["runs"][0]["results"][0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], file_uri(&root.join("test-data/lint-problems.drawio")));
+    }
+
+    #[test]
+    fn test_lint_test_data() {
+        let findings = lint_file(Path::new("test-data/nonce-truncation.drawio"), &BTreeMap::new()).unwrap();
+        assert!(findings.is_empty(), "unexpected findings {:?}", findings.iter().map(|f| f.to_string()).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,67 @@
+//! Per-output locks. Planned builds are deduplicated by output path, but as a safety net,
+//! two jobs must never write the same output at the same time
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+
+#[derive(Default)]
+pub struct OutputLocks {
+    held: Mutex<HashSet<PathBuf>>,
+    released: Condvar,
+}
+
+/// Releases the locked outputs when dropped
+pub struct OutputGuard<'a> {
+    locks: &'a OutputLocks,
+    paths: Vec<PathBuf>,
+}
+
+impl OutputLocks {
+    /// Block until none of `paths` is locked by another job, then lock all of them at once
+    pub fn lock(&self, paths: Vec<PathBuf>) -> OutputGuard<'_> {
+        let mut held = self.held.lock().unwrap();
+        while paths.iter().any(|v| held.contains(v)) {
+            held = self.released.wait(held).unwrap();
+        }
+        held.extend(paths.iter().cloned());
+        OutputGuard { locks: self, paths }
+    }
+}
+
+impl Drop for OutputGuard<'_> {
+    fn drop(&mut self) {
+        let mut held = self.locks.held.lock().unwrap();
+        for path in &self.paths {
+            held.remove(path);
+        }
+        self.locks.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lock_blocks_overlapping_outputs() {
+        let locks = OutputLocks::default();
+        let acquired = AtomicBool::new(false);
+        let guard = locks.lock(vec![PathBuf::from("a-0.png"), PathBuf::from("a-1.png")]);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _guard = locks.lock(vec![PathBuf::from("a-1.png")]);
+                acquired.store(true, Ordering::SeqCst);
+            });
+            //disjoint outputs are not blocked
+            drop(locks.lock(vec![PathBuf::from("b-0.png")]));
+            thread::sleep(Duration::from_millis(50));
+            assert!(!acquired.load(Ordering::SeqCst));
+            drop(guard);
+        });
+        assert!(acquired.load(Ordering::SeqCst));
+    }
+}
@@ -1,16 +1,60 @@
-use regex::Regex;
-use serde::Deserialize;
+#![allow(clippy::result_large_err)]
+
+mod archive;
+mod audit;
+mod bench;
+mod budget;
+mod crash;
+mod decompress;
+mod deps;
+mod diagram;
+mod diff;
+mod discover;
+mod extract_text;
+mod fmt;
+mod fonts;
+mod gallery;
+mod hook;
+mod journal;
+mod lint;
+mod locks;
+mod merge;
+mod mock;
+mod priority;
+mod progress;
+mod remote;
+mod report;
+mod runlog;
+mod split;
+mod state;
+mod stats;
+
+use budget::{Offenders, OversizeAction};
+use deps::Dependencies;
+use gallery::{GalleryFigure, GALLERY_FILE_NAME};
+use diagram::DrawioDocument;
+use journal::{FileJournal, Journal, JournalStatus};
+use locks::OutputLocks;
+use mock::Backend;
+use progress::{Progress, ProgressMode};
+use remote::{WorkerLease, WorkerPool};
+use report::{BuildReport, StepStatus};
+use runlog::RunLog;
+use serde::{Deserialize, Serialize};
 use snafu::prelude::*;
-use std::collections::HashMap;
+use state::{BuildState, Freshness};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, create_dir_all, File};
 use std::io::Write;
-use std::time::{Duration, SystemTime};
+use std::time::{Instant, SystemTime};
 use std::env;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, ExitStatus, Stdio};
-use clap::Parser;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use clap::{Parser, Subcommand};
 use rayon::prelude::*;
-use indicatif::{ProgressBar, ProgressStyle};
 
 
 #[derive(Debug,Snafu)]
@@ -23,7 +67,23 @@ struct DrawioError {
     stdout: Vec<u8>,
     ///If "None" we failed before terminating the program
     exit_code: Option<ExitStatus>,
+    ///If "None" we failed before starting drawio
+    command: Option<DrawioCommand>,
+    ///Crash dumps and logs left behind by electron, if drawio crashed
+    crash_artifacts: Vec<PathBuf>,
+}
+
+/// Everything needed to reproduce a drawio invocation
+#[derive(Debug,Clone,Serialize)]
+struct DrawioCommand {
+    argv: Vec<String>,
+    working_dir: PathBuf,
+    ///Variables set for drawio and inherited variables that commonly affect it
+    env: BTreeMap<String,String>,
 }
+
+/// Inherited environment variables that are recorded for failed drawio invocations
+const RELEVANT_ENV: &[&str] = &["PATH", "HOME", "DISPLAY", "WAYLAND_DISPLAY", "XDG_RUNTIME_DIR", "LANG", "LC_ALL"];
  
 #[derive(Debug, Snafu)]
 enum AppError {
@@ -44,11 +104,24 @@ enum AppError {
 }
 
 
+const DEFAULT_BUILD_ARGS: &str = "-x -f png -t -s 5";
+
 #[derive(Parser)]
-#[command(version,about,long_about=None)]
+#[command(version,about,long_about=None,args_conflicts_with_subcommands=true)]
 struct Args {
 
-    ///Path to folder with input files
+    ///If no command is given, the figures are built
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    build: BuildArgs,
+}
+
+#[derive(clap::Args)]
+struct BuildArgs {
+
+    ///Path to folder with input files, a single .drawio file or a .zip, .tar or .tar.gz archive with .drawio files
     #[arg(short,long,default_value="./")]
     input: String,
 
@@ -67,12 +140,121 @@ struct Args {
 
     ///Drawio build args. Separate flags and flag value with whitespaces.
     /// /// Don't forget to put the whole thing in quotes
-    #[arg(long,default_value="-x -f png -t -s 5")]
+    #[arg(long,default_value=DEFAULT_BUILD_ARGS)]
     build_args : String,
 
     ///Path to optional config file
     #[arg(long)]
     config: Option<String>,
+
+    ///Flag passed verbatim to electron/chromium, e.g. "--no-sandbox" or "--disable-gpu".
+    /// Appended after drawio's own arguments. Repeat for multiple flags
+    #[arg(long,allow_hyphen_values=true)]
+    electron_args: Vec<String>,
+
+    ///Write the text of each exported step to "<output>.alt.txt", e.g. for use as alt text on websites
+    #[arg(long,default_value="false")]
+    alt_text: bool,
+
+    ///Also write the html page "index.html" to the output folder, which shows all exported figures with their alt text
+    #[arg(long,default_value="false",requires="alt_text")]
+    gallery: bool,
+
+    ///Write a JUnit XML report to this path, in which each export step is a test case.
+    /// CI systems like GitLab can display it in their test report UI
+    #[arg(long)]
+    junit: Option<String>,
+
+    ///Experimental: export all steps of a file with a single drawio process instead of one process per step.
+    /// Faster, but needs staged copies of the input files in which only the layers of each step are visible
+    #[arg(long,default_value="false")]
+    batch: bool,
+
+    ///Export every page of multi-page files instead of only the first one.
+    /// The outputs of each page are named after the page instead of the file
+    #[arg(long,default_value="false")]
+    all_pages: bool,
+
+    ///Folder with translation files named "<locale>.json", each mapping label texts to their translation.
+    /// Every figure is additionally exported once per locale, e.g. as "figure-0.de.png"
+    #[arg(long)]
+    locales: Option<String>,
+
+    ///Run drawio with reduced CPU and I/O priority, to keep the machine responsive during long builds
+    #[arg(long,default_value="false")]
+    nice: bool,
+
+    ///Pin the drawio process of each parallel job to this many CPU cores (Linux only).
+    /// Each electron instance spawns many threads, which otherwise oversubscribe the CPU
+    #[arg(long)]
+    cpus_per_job: Option<usize>,
+
+    ///How to report the build progress. "auto" falls back to plain lines if the output is not a terminal, e.g. in CI
+    #[arg(long,value_enum,default_value_t=ProgressMode::Auto)]
+    progress: ProgressMode,
+
+    ///Log all decisions, executed commands, durations and drawio output of the build to this file,
+    /// e.g. to find out why a figure was (not) rebuilt
+    #[arg(long)]
+    log_file: Option<String>,
+
+    ///Experimental: JSON file with a list of SSH workers, e.g. [{"host": "user@build-1", "drawio": "drawio", "jobs": 2}].
+    /// Files are exported locally or on the worker expected to finish them first
+    #[arg(long,requires="batch")]
+    ssh_workers: Option<String>,
+
+    ///Number of files exported in parallel. Defaults to the recommendation of "bench" or, without one, the number of CPUs
+    #[arg(long)]
+    jobs: Option<usize>,
+
+    ///Also search sub folders of the input folder for .drawio files
+    #[arg(long,default_value="false")]
+    recursive: bool,
+
+    ///Skip input files excluded by .gitignore or .ignore files, e.g. backup copies or vendored examples
+    #[arg(long,default_value="false")]
+    respect_ignore_files: bool,
+
+    ///Resume an interrupted build: skip steps the journal records as complete and rebuild all others
+    #[arg(long,default_value="false")]
+    resume: bool,
+
+    ///What to do with outputs larger than "max_output_kb" from the config file
+    #[arg(long,value_enum,default_value_t=OversizeAction::Warn)]
+    oversize: OversizeAction,
+
+    ///"mock" writes placeholder images with the size of the exported figures instead of running drawio,
+    /// e.g. to test the LaTeX integration on machines without drawio
+    #[arg(long,value_enum,default_value_t=Backend::Drawio)]
+    backend: Backend,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    ///Dump all text labels per file, page and layer
+    ExtractText(extract_text::ExtractTextArgs),
+    ///Check diagrams for common problems before exporting them
+    Lint(lint::LintArgs),
+    ///Report the fonts used by the diagrams and warn about fonts that are not installed
+    Fonts(fonts::FontsArgs),
+    ///Summarize the number of files, pages, layers, shapes, export steps and the output size
+    Stats(stats::StatsArgs),
+    ///Compare two versions of a .drawio file page by page and layer by layer
+    Diff(diff::DiffArgs),
+    ///Git hooks, e.g. "hook pre-commit" to rebuild and stage the figures of staged .drawio files
+    Hook(hook::HookArgs),
+    ///Rewrite compressed .drawio files as plain XML, so that they can be diffed
+    Decompress(decompress::DecompressArgs),
+    ///Rewrite .drawio files with sorted attributes and without volatile editor metadata, to avoid churn in version control
+    Fmt(fmt::FmtArgs),
+    ///Combine several .drawio files into one multi-page file, with one page per source file
+    Merge(merge::MergeArgs),
+    ///Write each page of a multi-page .drawio file to its own file, named after the page
+    Split(split::SplitArgs),
+    ///Export a sample of figures with different numbers of parallel jobs and recommend the fastest for future builds
+    Bench(bench::BenchArgs),
+    ///Report exported figures that no .tex file uses and figures used by .tex files that do not exist
+    Audit(audit::AuditArgs),
 }
 
 #[derive(Deserialize,Debug)]
@@ -82,24 +264,77 @@ struct DrawioFileConfig {
     name: String,
     ///Specifies the order in which layers should be exported
     ///outer array: export steps, inner array: layers for that step
-    /// no append semantics; specify all layers for each step
-    order: Vec<Vec<u8>>,
+    /// no append semantics; specify all layers for each step.
+    /// If not set, layers are exported incrementally
+    order: Option<Vec<Vec<u8>>>,
+    ///Additional environment variables for the drawio processes of this file.
+    /// Overrides variables with the same name from the global config
+    env: Option<BTreeMap<String,String>>,
+    ///Values for "{{name}}" placeholders in the labels of this file.
+    /// Overrides variables with the same name from the global config
+    variables: Option<BTreeMap<String,String>>,
+    ///Size budget for each output of this file in KB. Overrides the global budget
+    max_output_kb: Option<u64>,
 }
 
 /// User specified tweaks for the build process
 #[derive(Default,Deserialize,Debug)]
 struct DrawioConfig {
+    ///Environment variables set for all spawned drawio processes
+    env: Option<BTreeMap<String,String>>,
+    ///Values for "{{name}}" placeholders in the labels of all files.
+    /// Placeholders without a value here are looked up in the environment
+    variables: Option<BTreeMap<String,String>>,
     ///Config overrides for individual drawio files
     inidividual_configs : Option<Vec<DrawioFileConfig>>,
+    ///Severity overrides for lint rules, keyed by rule name
+    lint: Option<BTreeMap<String,lint::Severity>>,
+    ///Size budget for each output in KB, e.g. to meet the limits of a journal
+    max_output_kb: Option<u64>,
 }
 
-struct DrawioProcess {
+fn load_config(path: Option<&str>) -> Result<DrawioConfig,AppError> {
+    match path {
+        Some(path) => {
+            Ok(serde_json::from_reader(File::open(path).whatever_context::<String,AppError>(format!("Failed to open config file {}",path))?).whatever_context::<&str,AppError>("Failed to parse config file")?)
+        },
+        None => Ok(DrawioConfig::default()),
+    }
+}
+
+/// Translations of label texts, keyed by locale
+type Translations = BTreeMap<String,BTreeMap<String,String>>;
+
+/// Load all "<locale>.json" files in `dir`
+fn load_translations(dir: Option<&str>) -> Result<Translations,AppError> {
+    let mut translations = Translations::new();
+    let Some(dir) = dir else {
+        return Ok(translations);
+    };
+    for entry in fs::read_dir(dir).whatever_context::<String,AppError>(format!("error listing translation files in folder {}", dir))? {
+        let path = entry.whatever_context::<String,AppError>(format!("error listing translation files in folder {}", dir))?.path();
+        if path.extension().is_none_or(|v| v != "json") {
+            continue;
+        }
+        let locale = path.file_stem().unwrap().to_str().unwrap().to_string();
+        let file = File::open(&path).whatever_context::<String,AppError>(format!("Failed to open translation file {:?}",path))?;
+        let map = serde_json::from_reader(file).whatever_context::<String,AppError>(format!("Failed to parse translation file {:?}",path))?;
+        translations.insert(locale, map);
+    }
+    Ok(translations)
+}
+
+/// A single output image that needs to be (re)built
+struct ExportStep {
+    ///Index of the step in the file's LayerConfig
+    idx: usize,
+    ///Layers visible in this step
+    layers: Vec<usize>,
     output_path: PathBuf,
-    input_path: PathBuf,
     old_modified_time: Option<SystemTime>,
-    handle: Child
 }
 
+#[derive(Clone)]
 enum LayerConfig {
     ///Number of layers. Exports [0],[0,1],[0,1,2]...
     Incremental(usize),
@@ -108,192 +343,714 @@ enum LayerConfig {
     /// and layers 0,1 in second step
     Custom(Vec<Vec<u8>>),
 }
+#[derive(Clone)]
 struct BuildConfig {
     ///general flags that or passed to drawio. DO NOT pass layer configs here
     flags: Vec<String>,
-    layer_config: LayerConfig
+    ///environment variables for the drawio processes
+    env: BTreeMap<String,String>,
+    layer_config: LayerConfig,
+    ///Values of the placeholders used in the file. Substituted in a temporary copy before exporting
+    variables: BTreeMap<String,String>,
+    ///Locale and label translations, if this is a localized export of the file
+    locale: Option<(String,BTreeMap<String,String>)>,
+    ///Name of the exported page, if this is one of several pages of a file that are exported separately.
+    /// Outputs are named after the page instead of the file
+    page: Option<String>,
+    ///Size budget for each output in KB
+    max_output_kb: Option<u64>,
 }
 
+/// Settings and shared state that are the same for all files of a build
+struct BuildContext<'a> {
+    drawio_binary: &'a str,
+    ///passed to electron after all drawio arguments
+    electron_args: &'a [String],
+    out_dir: &'a str,
+    ///If true, export all steps of a file with a single drawio process
+    batch: bool,
+    ///If true, write alt text sidecars for all exported images
+    alt_text: bool,
+    ///If true, run drawio with reduced priority
+    nice: bool,
+    ///Number of CPU cores each job's drawio processes are pinned to
+    cpus_per_job: Option<usize>,
+    ///CPUs we may pin to, read once at the start of the build
+    allowed_cpus: &'a [usize],
+    ///Set once we warned that pinning failed, so that we do not warn for every process
+    pin_warned: &'a AtomicBool,
+    progress: &'a Progress,
+    state: &'a BuildState,
+    report: &'a BuildReport,
+    locks: &'a OutputLocks,
+    log: &'a RunLog,
+    ///Set by the first job that exports something. Contains the error if the drawio warmup failed
+    warmup: &'a OnceLock<Option<DrawioError>>,
+    ///Local machine and remote workers for batched exports
+    workers: &'a WorkerPool,
+    dependencies: &'a Dependencies,
+    ///Completed steps of this build, to resume it if it is interrupted
+    journal: &'a Journal,
+    oversize: OversizeAction,
+    offenders: &'a Offenders,
+    backend: Backend,
+}
 
-/// Convert LayerConfig to strings that can be passed to the drawio cli
-fn assemble_layer_cli_flag(config: &LayerConfig) -> Vec<String> {
-    let mut result = Vec::new();
+
+/// Expand LayerConfig to the layer indices of each export step
+fn layer_steps(config: &LayerConfig) -> Vec<Vec<usize>> {
     match config {
         LayerConfig::Incremental(layer_count) => {
-            let mut buf: String  = "0".to_string();
-            result.push(buf.clone());
-            for layer in 1..*layer_count {
-                buf += &format!(",{}",layer); 
-                result.push(buf.clone());
-            }
-            result
+            (1..=*layer_count).map(|step| (0..step).collect()).collect()
         },
         LayerConfig::Custom(v) => {
-            v.iter().map(|inner| inner.iter().map(|num| format!("{}",num)).collect::<Vec<String>>().join(",")).collect()
-        
+            v.iter().map(|inner| inner.iter().map(|num| *num as usize).collect()).collect()
         },
     }
 }
 
-fn run_command(drawio_binary : &str, file: &PathBuf, config: &BuildConfig, out_dir: &str,progress : &ProgressBar) -> Result<(),DrawioError> {
-    // Build the command
-    let file_name = file.file_stem().unwrap().to_str().unwrap();
-    let full_file_path = file.as_path().as_os_str().to_str().unwrap();
+/// Convert LayerConfig to strings that can be passed to the drawio cli
+fn assemble_layer_cli_flag(config: &LayerConfig) -> Vec<String> {
+    layer_steps(config).iter().map(|inner| inner.iter().map(|num| format!("{}",num)).collect::<Vec<String>>().join(",")).collect()
+}
 
-    let mut handles = Vec::new();
-    let export_steps = assemble_layer_cli_flag(&config.layer_config);
-    // Add the file and flags to the command
-    for (idx,step) in export_steps.iter().enumerate() {
+/// Replace all characters that are not allowed in file names on all platforms with "_".
+/// Everything else, e.g. spaces, is kept, so that names that are valid file names stay the same
+fn sanitize_file_name(name: &str) -> String {
+    name.trim().chars().map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c }).collect()
+}
 
-        let mut command = Command::new(drawio_binary);
+/// Name of the outputs of `file` without step index and extension
+fn output_stem(file: &Path, config: &BuildConfig) -> String {
+    match &config.page {
+        Some(page) => sanitize_file_name(page),
+        None => file.file_stem().unwrap().to_str().unwrap().to_string(),
+    }
+}
 
-        let output_path = Path::new(out_dir).join(format!("{}-{}.png",file_name,idx));
+/// Key under which the build state of `file` is stored
+fn state_key(file: &Path, config: &BuildConfig) -> String {
+    let mut key = file.file_name().unwrap().to_str().unwrap().to_string();
+    if let Some(page) = &config.page {
+        key = format!("{}#{}",key,page);
+    }
+    if let Some((locale,_)) = &config.locale {
+        key = format!("{}.{}",key,locale);
+    }
+    key
+}
+
+fn output_path(file: &Path, config: &BuildConfig, out_dir: &str, step_idx: usize) -> PathBuf {
+    let file_name = match &config.locale {
+        Some((locale,_)) => format!("{}-{}.{}.png",output_stem(file, config),step_idx,locale),
+        None => format!("{}-{}.png",output_stem(file, config),step_idx),
+    };
+    Path::new(out_dir).join(file_name)
+}
 
-        //skip build if output file is older than input file, i.e. no changes since built
+/// Export steps of `file` whose output is missing or, depending on `freshness`, outdated.
+/// `sources` are `file` and its dependencies
+fn plan_export_steps(file: &Path, sources: &[PathBuf], config: &BuildConfig, out_dir: &str, freshness: &Freshness) -> Vec<ExportStep> {
+    let in_modified = sources.iter().filter_map(|v| v.metadata().and_then(|m| m.modified()).ok()).max().unwrap();
+    let mut steps = Vec::new();
+    for (idx,layers) in layer_steps(&config.layer_config).into_iter().enumerate() {
+        let output_path = output_path(file, config, out_dir, idx);
+
+        //skip build if output file is older than input file and its dependencies, i.e. no changes since built
         let mut old_modified_time = None;
         if output_path.exists() {
             let out_modified = output_path.metadata().unwrap().modified().unwrap();
-            let in_modified = file.metadata().unwrap().modified().unwrap();
-            if out_modified.ge(&in_modified) {
+            let up_to_date = match freshness {
+                Freshness::Timestamp => out_modified.ge(&in_modified),
+                Freshness::Exists => true,
+                Freshness::Rebuild(_) => false,
+            };
+            if up_to_date {
                 continue;
             }
             old_modified_time = Some(out_modified);
         }
-        
-        command.args(&config.flags).arg("-o").arg(&output_path);
-        command.arg("--layers");
-        command.arg(&step);
-        
-        command.arg(full_file_path);
-        command.stdout(Stdio::piped());
-        command.stderr(Stdio::piped());
-        command.current_dir(env::current_dir().map_err(|e| DrawioError{
-            message: format!("failed to spawn drawio process : {:?}",e).to_string(),
-            input_path: PathBuf::from(full_file_path),
-            output_path: output_path.clone(),
-            stderr: Vec::new(),
-            stdout: Vec::new(),
-            exit_code: None,
-        })?);
-
-    
-        handles.push(DrawioProcess{
-            output_path: output_path.clone(),
-            input_path: PathBuf::from(full_file_path),
-            old_modified_time,
-            handle: command.spawn().map_err(|e| DrawioError{
-                message: format!("failed to spawn drawio process : {:?}",e).to_string(),
-                input_path: PathBuf::from(full_file_path),
-                output_path : output_path.clone(),
-                stderr: Vec::new(),
-                stdout: Vec::new(),
-                exit_code: None,
-            })?,
+        steps.push(ExportStep { idx, layers, output_path, old_modified_time });
+    }
+    steps
+}
+
+/// A running drawio process
+struct DrawioProcess {
+    child: Child,
+    command: DrawioCommand,
+    started: Instant,
+    ///True for the local drawio, false for e.g. ssh. Only electron leaves crash dumps behind
+    electron: bool,
+}
+
+fn spawn_drawio(ctx: &BuildContext, args: Vec<&OsStr>, env: &BTreeMap<String,String>, input_path: &Path, output_path: &Path) -> Result<DrawioProcess,DrawioError> {
+    let args = args.into_iter().chain(ctx.electron_args.iter().map(OsStr::new)).collect();
+    let process = spawn_process(ctx, OsStr::new(ctx.drawio_binary), args, env, input_path, output_path)?;
+    Ok(DrawioProcess { electron: true, ..process })
+}
+
+/// Spawn `program` with the priority and CPU affinity of drawio processes, e.g. ssh for remote builds
+fn spawn_process(ctx: &BuildContext, program: &OsStr, args: Vec<&OsStr>, env: &BTreeMap<String,String>, input_path: &Path, output_path: &Path) -> Result<DrawioProcess,DrawioError> {
+    let spawn_error = |e: std::io::Error, command: Option<&DrawioCommand>| DrawioError{
+        message: format!("failed to spawn {} process : {:?}",program.to_string_lossy(),e).to_string(),
+        input_path: input_path.to_path_buf(),
+        output_path: output_path.to_path_buf(),
+        stderr: Vec::new(),
+        stdout: Vec::new(),
+        exit_code: None,
+        command: command.cloned(),
+        crash_artifacts: Vec::new(),
+    };
+    let working_dir = env::current_dir().map_err(|e| spawn_error(e, None))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    command.envs(env);
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    command.current_dir(&working_dir);
+    if ctx.nice {
+        priority::lower_priority(&mut command);
+    }
+    //rayon runs one job per worker thread at a time, so the thread index identifies the job slot
+    let job_cpus = ctx.cpus_per_job.map(|v| priority::job_cpus(rayon::current_thread_index().unwrap_or(0), v, ctx.allowed_cpus));
+    if let Some(cpus) = &job_cpus {
+        priority::pin_to_cpus(&mut command, cpus);
+    }
+    let mut recorded_env: BTreeMap<String,String> = RELEVANT_ENV.iter().filter_map(|name| Some((name.to_string(),env::var(name).ok()?))).collect();
+    recorded_env.extend(env.clone());
+    let drawio_command = DrawioCommand{
+        argv: [command.get_program()].into_iter().chain(command.get_args()).map(|v| v.to_string_lossy().into_owned()).collect(),
+        working_dir,
+        env: recorded_env,
+    };
+    ctx.log.log(format!("running for {:?} : {} (env {:?})",output_path,drawio_command.argv.join(" "),env));
+    let child = command.spawn().map_err(|e| spawn_error(e, Some(&drawio_command)))?;
+    if let Some(cpus) = job_cpus {
+        check_pinned(ctx, child.id(), &cpus);
+    }
+    Ok(DrawioProcess { child, command: drawio_command, started: Instant::now(), electron: false })
+}
+
+/// Warn once if the process `pid` has not been pinned to `cpus`. Pinning happens in the child, which cannot report errors
+#[cfg(target_os = "linux")]
+fn check_pinned(ctx: &BuildContext, pid: u32, cpus: &[usize]) {
+    //the process may already have exited, in which case there is nothing to check
+    let Ok(pinned) = priority::affinity(pid) else {
+        return;
+    };
+    if pinned != cpus && !ctx.pin_warned.swap(true, Ordering::Relaxed) {
+        let warning = format!("failed to pin drawio to CPUs {:?}, it runs on CPUs {:?}",cpus,pinned);
+        ctx.log.log(&warning);
+        ctx.progress.warn(&warning);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_pinned(_ctx: &BuildContext, _pid: u32, _cpus: &[usize]) {}
+
+/// Wait for `process` to finish. Returns its output and the command it was started with
+fn wait_drawio(ctx: &BuildContext, process: DrawioProcess, input_path: &Path, output_path: &Path) -> Result<(Output,DrawioCommand),DrawioError> {
+    let result = process.child.wait_with_output();
+    if let Ok(output) = &result {
+        ctx.log.log(format!("drawio for {:?} finished after {:.2}s with {}",output_path,process.started.elapsed().as_secs_f64(),output.status));
+        ctx.log.log_output("stdout", &output.stdout);
+        ctx.log.log_output("stderr", &output.stderr);
+    }
+    let output = result.map_err(|e| DrawioError{
+        message: format!("process termination error : {:?}",e).to_string(),
+        input_path: input_path.to_path_buf(),
+        output_path: output_path.to_path_buf(),
+        stderr: Vec::new(),
+        stdout: Vec::new(),
+        exit_code: None,
+        command: Some(process.command.clone()),
+        crash_artifacts: Vec::new(),
+    })?;
+    if !output.status.success() {
+        let (message,crash_artifacts) = match crash::crashed(&output.status) {
+            true if process.electron => {
+                let started = SystemTime::now() - process.started.elapsed();
+                let crash_artifacts = crash::collect_artifacts(started);
+                ctx.log.log(format!("drawio for {:?} crashed, crash dumps and logs : {:?}",output_path,crash_artifacts));
+                (format!("drawio crashed ({})",output.status),crash_artifacts)
+            },
+            true => (format!("{} crashed ({})",process.command.argv[0],output.status),Vec::new()),
+            false => ("error exit code".to_string(),Vec::new()),
+        };
+        return Err(DrawioError{
+            message,
+            input_path: input_path.to_path_buf(),
+            output_path: output_path.to_path_buf(),
+            stderr: output.stderr,
+            stdout: output.stdout,
+            exit_code: Some(output.status),
+            command: Some(process.command),
+            crash_artifacts,
         });
     }
+    Ok((output,process.command))
+}
 
-    for x in handles {
-         // Execute the command
-         let output = x.handle.wait_with_output().map_err(|e| DrawioError{
-            message: format!("process termination error : {:?}",e).to_string(),
-            input_path: x.input_path.clone(),
-            output_path: x.output_path.clone(),
-            stderr: Vec::new(),
-            stdout: Vec::new(),
-            exit_code: None,
-        })?;
-        progress.inc(1);
-        let mut error_template = DrawioError{
-            message: "generic error".to_string(),
-            input_path: x.input_path.clone(),
-            output_path: x.output_path.clone(),
+/// Drawio's exit code does not reflect if there has been an error.
+/// For now, we assume that if the output file got created/updated everything succeeded
+fn check_output(step: &ExportStep, input_path: &Path, output: &Output, command: &DrawioCommand) -> Result<(),DrawioError> {
+    let message = match step.old_modified_time {
+        Some(old_modified_time) => {
+            let new_modified_time = step.output_path.metadata().unwrap().modified().unwrap();
+            if old_modified_time.lt(&new_modified_time) {
+                return Ok(());
+            }
+            "output file was not updated"
+        },
+        //file did not previously exist
+        None => {
+            if step.output_path.exists() {
+                return Ok(());
+            }
+            "output file was not created"
+        },
+    };
+    Err(DrawioError{
+        message: message.to_string(),
+        input_path: input_path.to_path_buf(),
+        output_path: step.output_path.clone(),
+        stderr: output.stderr.clone(),
+        stdout: output.stdout.clone(),
+        exit_code: Some(output.status),
+        command: Some(command.clone()),
+        crash_artifacts: Vec::new(),
+    })
+}
+
+/// Spawn one drawio process per export step.
+/// If the file has placeholders or is localized, all steps are exported from a staged copy with the labels substituted
+fn run_per_step(ctx: &BuildContext, file: &Path, config: &BuildConfig, steps: &[ExportStep], journal: &FileJournal) -> Result<(),DrawioError> {
+    ensure_warmed_up(ctx, file)?;
+    let staging_dir = staging_dir(file, config);
+    let mut input_path = file.to_path_buf();
+    if !config.variables.is_empty() || config.locale.is_some() {
+        let document = load_substituted(file, config, &staging_dir)?;
+        input_path = staging_dir.join(file.file_name().unwrap());
+        document.save(&input_path).map_err(|e| staging_error(file, &staging_dir, format!("failed to stage substituted copy : {}",snafu::Report::from_error(e))))?;
+    }
+    let result = spawn_per_step(ctx, file, &input_path, config, steps, journal);
+    if input_path != file {
+        let _ = fs::remove_dir_all(&staging_dir);
+    }
+    result
+}
+
+fn spawn_per_step(ctx: &BuildContext, file: &Path, input_path: &Path, config: &BuildConfig, steps: &[ExportStep], journal: &FileJournal) -> Result<(),DrawioError> {
+    let layer_flags = assemble_layer_cli_flag(&config.layer_config);
+    let mut handles = Vec::new();
+    for step in steps {
+        let mut args: Vec<&OsStr> = config.flags.iter().map(OsStr::new).collect();
+        args.extend([OsStr::new("-o"), step.output_path.as_os_str(), OsStr::new("--layers"), OsStr::new(&layer_flags[step.idx]), input_path.as_os_str()]);
+        journal.started(&step.output_path).map_err(|e| journal_error(file, &step.output_path, e))?;
+        let handle = spawn_drawio(ctx, args, &config.env, file, &step.output_path)?;
+        handles.push((step,handle));
+    }
+
+    for (step,handle) in handles {
+        let (output,command) = wait_drawio(ctx, handle, file, &step.output_path)?;
+        check_output(step, file, &output, &command)?;
+        journal.completed(&step.output_path).map_err(|e| journal_error(file, &step.output_path, e))?;
+    }
+    Ok(())
+}
+
+fn journal_error(file: &Path, output_path: &Path, e: std::io::Error) -> DrawioError {
+    staging_error(file, output_path, format!("failed to write build journal : {:?}",e))
+}
+
+/// Smallest diagram that drawio exports to an image
+const WARMUP_DIAGRAM: &str = r#"<mxfile><diagram id="warmup" name="Page-1"><mxGraphModel><root><mxCell id="0"/><mxCell id="1" parent="0"/><mxCell id="2" value="" style="rounded=0;" vertex="1" parent="1"><mxGeometry width="10" height="10" as="geometry"/></mxCell></root></mxGraphModel></diagram></mxfile>"#;
+
+/// Export a tiny diagram once before the parallel exports. The first electron start creates the profile
+/// and caches and is much slower than later starts, so that many concurrent first starts tend to time out
+fn warm_up(ctx: &BuildContext) -> Result<(),DrawioError> {
+    let dir = staging_root().join("warmup");
+    let input_path = dir.join("warmup.drawio");
+    let output_path = dir.join("warmup.png");
+    create_dir_all(&dir).map_err(|e| staging_error(&input_path, &dir, format!("failed to create staging dir : {:?}",e)))?;
+    fs::write(&input_path, WARMUP_DIAGRAM).map_err(|e| staging_error(&input_path, &dir, format!("failed to write warmup diagram : {:?}",e)))?;
+    ctx.log.log("warming up drawio");
+    let args = vec![OsStr::new("-x"),OsStr::new("-f"),OsStr::new("png"),OsStr::new("-o"),output_path.as_os_str(),input_path.as_os_str()];
+    let process = spawn_drawio(ctx, args, &BTreeMap::new(), &input_path, &output_path)?;
+    let (output,command) = wait_drawio(ctx, process, &input_path, &output_path)?;
+    if !output_path.exists() {
+        return Err(DrawioError{
+            message: "drawio did not export the warmup diagram".to_string(),
+            input_path,
+            output_path,
             stderr: output.stderr,
             stdout: output.stdout,
-            exit_code: None,
-        };
-         if !output.status.success() {
-            error_template.message = "error exit code".to_string();
-             return Err(error_template);
-         }
-       
-         //drawio's exit code does not reflect if there has been an error
-         //For now, we assume that if the output file got created/updated everything succeeded
-         match x.old_modified_time {
-            Some(old_modified_time) => {
-                let new_modified_time = x.output_path.metadata().unwrap().modified().unwrap();
-                if old_modified_time.ge(&new_modified_time) {
-                    error_template.message = "output file was not updated".to_string();
-                    return Err(error_template)
-                }
-            },
-            //file did not previously exist
-            None => {
-                if !x.output_path.exists() {
-                    error_template.message = "output file was not created".to_string();
-                    return Err(error_template);
-                }
-            },
-        };
- 
+            exit_code: Some(output.status),
+            command: Some(command),
+            crash_artifacts: Vec::new(),
+        });
     }
     Ok(())
+}
 
+/// Warm up the local drawio before its first export. Builds that export everything on SSH workers never
+/// start the local drawio. The first job to get here warms drawio up, all others wait for it
+fn ensure_warmed_up(ctx: &BuildContext, file: &Path) -> Result<(),DrawioError> {
+    match ctx.warmup.get_or_init(|| warm_up(ctx).err()) {
+        Some(_) => Err(staging_error(file, file, "skipped, drawio warmup failed".to_string())),
+        None => Ok(()),
+    }
 }
 
-fn main() -> Result<(), AppError> {
+/// Folder for the modified input copies of builds
+fn staging_root() -> PathBuf {
+    env::temp_dir().join(format!("drawio-builder-{}",std::process::id()))
+}
 
-    let args = Args::parse();
+/// Folder for the modified input copies of `file` with `config`. Unique among all BuildConfigs of a build
+fn staging_dir(file: &Path, config: &BuildConfig) -> PathBuf {
+    let output_name = output_path(file, config, "", 0);
+    staging_root().join(output_name.file_stem().unwrap())
+}
 
-    let  mut drawio_flags : Vec<String> = args.build_args.split(" ").map(|v| v.to_string()).collect();
+/// Translations are applied first, so that translated labels may contain placeholders as well
+fn substitute_labels(document: &mut DrawioDocument, config: &BuildConfig) {
+    if let Some((_,translations)) = &config.locale {
+        document.translate(translations);
+    }
+    document.substitute_placeholders(&config.variables);
+}
 
-    //If draft mode, change scale to 1
-    if args.draft {
-        let mut scale_flag_idx = None;
-        for (i,v) in drawio_flags.iter().enumerate() {
-            if v == "-s" || v == "--scale" {
-                scale_flag_idx = Some(i);
-                break;
-            }
-        }
-        if let Some(idx) = scale_flag_idx {
-            if drawio_flags.len() < idx + 1 {
-                whatever!("Scale flag does not have argument");
-            }
-            drawio_flags[idx+1] = "1".to_string();
-        }
+fn staging_error(file: &Path, staging_dir: &Path, message: String) -> DrawioError {
+    DrawioError{
+        message,
+        input_path: file.to_path_buf(),
+        output_path: staging_dir.to_path_buf(),
+        stderr: Vec::new(),
+        stdout: Vec::new(),
+        exit_code: None,
+        command: None,
+        crash_artifacts: Vec::new(),
     }
+}
 
-    let config: DrawioConfig = match args.config {
-        Some(path) => {
-            serde_json::from_reader(File::open(&path).whatever_context::<String,AppError>(format!("Failed to open config file {}",path))?).whatever_context::<&str,AppError>("Failed to parse config file")?
+/// Load `file` with translations and placeholders substituted and create an empty `staging_dir` for modified copies
+fn load_substituted(file: &Path, config: &BuildConfig, staging_dir: &Path) -> Result<DrawioDocument,DrawioError> {
+    let mut document = DrawioDocument::load(file).map_err(|e| staging_error(file, staging_dir, format!("failed to parse drawio file : {}",snafu::Report::from_error(e))))?;
+    substitute_labels(&mut document, config);
+    //the copy is exported from another folder, in which relative links and images would not resolve
+    let source_dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let source_dir = source_dir.canonicalize().map_err(|e| staging_error(file, staging_dir, format!("failed to resolve folder of drawio file : {:?}",e)))?;
+    document.resolve_relative_references(&source_dir);
+    //stale copies from an earlier, interrupted run would get exported as well
+    if staging_dir.exists() {
+        fs::remove_dir_all(staging_dir).map_err(|e| staging_error(file, staging_dir, format!("failed to clean staging dir : {:?}",e)))?;
+    }
+    create_dir_all(staging_dir).map_err(|e| staging_error(file, staging_dir, format!("failed to create staging dir : {:?}",e)))?;
+    Ok(document)
+}
+
+/// Export all steps of `file` with a single drawio process. For each step, we stage a copy
+/// of `file` in which only the layers of that step are visible. Drawio then exports the whole
+/// staging folder in one go, saving us the electron start-up time for all but the first step
+fn run_batched(ctx: &BuildContext, file: &Path, config: &BuildConfig, steps: &[ExportStep], worker: &WorkerLease, journal: &FileJournal) -> Result<(),DrawioError> {
+    let staging_dir = staging_dir(file, config);
+    let mut document = load_substituted(file, config, &staging_dir)?;
+    for step in steps {
+        document.set_visible_layers(&step.layers);
+        //drawio names the output after the input, so the staged copy gets the final output's name
+        let staged_path = staging_dir.join(step.output_path.with_extension("drawio").file_name().unwrap());
+        document.save(&staged_path).map_err(|e| staging_error(file, &staging_dir, format!("failed to stage export step {} : {}",step.idx,snafu::Report::from_error(e))))?;
+        journal.started(&step.output_path).map_err(|e| journal_error(file, &step.output_path, e))?;
+    }
 
+    let output = match worker.remote() {
+        Some(remote) => remote::export(ctx, remote, file, config, &staging_dir),
+        None => {
+            let mut args: Vec<&OsStr> = config.flags.iter().map(OsStr::new).collect();
+            args.extend([OsStr::new("-o"), OsStr::new(ctx.out_dir), staging_dir.as_os_str()]);
+            ensure_warmed_up(ctx, file)
+                .and_then(|_| spawn_drawio(ctx, args, &config.env, file, Path::new(ctx.out_dir)))
+                .and_then(|handle| wait_drawio(ctx, handle, file, Path::new(ctx.out_dir)))
         },
-        None => DrawioConfig::default(),
     };
+    let _ = fs::remove_dir_all(&staging_dir);
+    let (output,command) = output?;
 
+    for step in steps {
+        check_output(step, file, &output, &command)?;
+        journal.completed(&step.output_path).map_err(|e| journal_error(file, &step.output_path, e))?;
+    }
+    Ok(())
+}
 
-    //Later we need to quickly check if there is a config override for a given file
-    let mut file_to_config :HashMap<String, &DrawioFileConfig> = HashMap::new();
-    if let Some(overrides) = &config.inidividual_configs {
-        for x in overrides {
-            file_to_config.insert(x.name.clone(), x);
+/// Write placeholder images for `steps` instead of exporting them with drawio
+fn run_mock(ctx: &BuildContext, file: &Path, config: &BuildConfig, steps: &[ExportStep], journal: &FileJournal) -> Result<(),DrawioError> {
+    for step in steps {
+        ctx.log.log(format!("writing placeholder {:?}",step.output_path));
+        journal.started(&step.output_path).map_err(|e| journal_error(file, &step.output_path, e))?;
+        mock::export(file, exported_page(&config.flags), &step.layers, &config.flags, &step.output_path)
+            .map_err(|e| staging_error(file, &step.output_path, format!("failed to write placeholder : {}",e)))?;
+        journal.completed(&step.output_path).map_err(|e| journal_error(file, &step.output_path, e))?;
+    }
+    Ok(())
+}
+
+fn alt_text_path(output_path: &Path) -> PathBuf {
+    let mut alt_path = output_path.to_path_buf().into_os_string();
+    alt_path.push(".alt.txt");
+    PathBuf::from(alt_path)
+}
+
+/// Index of the page drawio exports with the given flags
+fn exported_page(flags: &[String]) -> usize {
+    flags.iter()
+        .position(|v| v == "-p" || v == "--page-index")
+        .and_then(|idx| flags.get(idx+1)?.parse().ok())
+        .unwrap_or(0)
+}
+
+/// `flags` with the page index set to `page`, replacing any page index the user passed
+fn with_page(flags: &[String], page: usize) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut iter = flags.iter();
+    while let Some(flag) = iter.next() {
+        if flag == "-p" || flag == "--page-index" {
+            iter.next();
+        } else {
+            result.push(flag.clone());
         }
     }
+    result.extend(["-p".to_string(), page.to_string()]);
+    result
+}
 
+/// Write the text of each export step next to its output. Sidecars are only (re)written
+/// if the step has just been built or if the sidecar is missing
+fn write_alt_texts(file: &Path, config: &BuildConfig, out_dir: &str, built: &[ExportStep]) -> Result<(),DrawioError> {
+    let mut document = None;
+    for (idx,layers) in layer_steps(&config.layer_config).into_iter().enumerate() {
+        let alt_path = alt_text_path(&output_path(file, config, out_dir, idx));
+        if alt_path.exists() && !built.iter().any(|s| s.idx == idx) {
+            continue;
+        }
 
+        let alt_error = |message: String| DrawioError{
+            message,
+            input_path: file.to_path_buf(),
+            output_path: alt_path.clone(),
+            stderr: Vec::new(),
+            stdout: Vec::new(),
+            exit_code: None,
+            command: None,
+            crash_artifacts: Vec::new(),
+        };
+        if document.is_none() {
+            let mut loaded = DrawioDocument::load(file).map_err(|e| alt_error(format!("failed to parse drawio file : {}",snafu::Report::from_error(e))))?;
+            substitute_labels(&mut loaded, config);
+            document = Some(loaded);
+        }
+        let pages = document.as_ref().unwrap().pages();
+        let text = pages.get(exported_page(&config.flags)).map(|page| page.text_labels(&layers).join("\n")).unwrap_or_default();
+        fs::write(&alt_path, text).map_err(|e| alt_error(format!("failed to write alt text : {:?}",e)))?;
+    }
+    Ok(())
+}
 
-    let drawio_path = match args.drawio {
-        Some(v) => v,
-        None => "drawio".to_string(),
+/// Write the gallery of all outputs of `drawio_files`, with the alt texts of their sidecars
+fn write_gallery(drawio_files: &[(PathBuf,BuildConfig)], out_dir: &str) -> Result<(),AppError> {
+    let figures: Vec<GalleryFigure> = drawio_files.iter().map(|(file,config)| GalleryFigure{
+        title: match &config.locale {
+            Some((locale,_)) => format!("{} ({})",describe_figure(file, config),locale),
+            None => describe_figure(file, config),
+        },
+        images: (0..layer_steps(&config.layer_config).len()).map(|idx| {
+            let output = output_path(file, config, out_dir, idx);
+            let alt_text = fs::read_to_string(alt_text_path(&output)).unwrap_or_default();
+            (output,alt_text)
+        }).collect(),
+    }).collect();
+    let path = Path::new(out_dir).join(GALLERY_FILE_NAME);
+    fs::write(&path, gallery::render(&figures)).whatever_context::<String,AppError>(format!("Failed to write gallery to {:?}",path))
+}
+
+/// Maximum number of re-exports at a lower scale until an output fits its budget
+const MAX_RESCALE_ATTEMPTS: usize = 3;
+
+/// Check the outputs of the built `steps` against the size budget of `config`. Depending on `--oversize`,
+/// outputs that exceed it are re-exported at a lower scale or optimized. Those that still exceed it are reported
+/// Export `steps` of `file`, either all of them with one drawio process on the worker expected to finish first
+/// or with one local drawio process per step
+fn export_steps(ctx: &BuildContext, file: &Path, config: &BuildConfig, steps: &[ExportStep], journal: &FileJournal) -> Result<(),DrawioError> {
+    if !ctx.batch {
+        return run_per_step(ctx, file, config, steps, journal);
+    }
+    let worker = ctx.workers.acquire(steps.len());
+    ctx.log.log(format!("exporting {:?} on {}",file,worker.name()));
+    let result = run_batched(ctx, file, config, steps, &worker, journal);
+    if result.is_ok() {
+        worker.succeeded();
+    }
+    result
+}
+
+fn enforce_budget(ctx: &BuildContext, file: &Path, config: &BuildConfig, steps: &[ExportStep], journal: &FileJournal) -> Result<(),DrawioError> {
+    let Some(max_kb) = config.max_output_kb else {
+        return Ok(());
     };
+    for step in steps {
+        let mut size_kb = budget::size_kb(&step.output_path);
+        let mut flags = config.flags.clone();
+        let mut attempts = 0;
+        //drawio ignores the scale if the size is given explicitly
+        let fixed_size = flags.iter().any(|v| v == "--width" || v == "--height");
+        if fixed_size && size_kb > max_kb && ctx.oversize == OversizeAction::Rescale {
+            ctx.log.log(format!("not re-exporting {:?} at a lower scale: --width and --height take precedence over the scale",step.output_path));
+        }
+        while !fixed_size && size_kb > max_kb && ctx.oversize == OversizeAction::Rescale && attempts < MAX_RESCALE_ATTEMPTS {
+            let Some((scale,reduced_flags)) = budget::reduced_scale(&flags, size_kb, max_kb) else {
+                break;
+            };
+            ctx.log.log(format!("re-exporting {:?} at scale {}: {} KB exceed the budget of {} KB",step.output_path,scale,size_kb,max_kb));
+            let rescaled = BuildConfig{
+                flags: reduced_flags.clone(),
+                ..config.clone()
+            };
+            let old_modified_time = step.output_path.metadata().and_then(|v| v.modified()).ok();
+            //without a local drawio, this has to run on the SSH workers as well
+            export_steps(ctx, file, &rescaled, &[ExportStep{ idx: step.idx, layers: step.layers.clone(), output_path: step.output_path.clone(), old_modified_time }], journal)?;
+            size_kb = budget::size_kb(&step.output_path);
+            flags = reduced_flags;
+            attempts += 1;
+        }
+        if size_kb > max_kb && ctx.oversize == OversizeAction::Optimize {
+            if step.output_path.extension().is_some_and(|v| v == "png") {
+                match budget::optimize_png(&step.output_path) {
+                    Ok(optimized_kb) => {
+                        ctx.log.log(format!("optimized {:?} from {} KB to {} KB",step.output_path,size_kb,optimized_kb));
+                        journal.completed(&step.output_path).map_err(|e| journal_error(file, &step.output_path, e))?;
+                        size_kb = optimized_kb;
+                    },
+                    Err(e) => ctx.log.log(format!("failed to optimize {:?} : {}",step.output_path,e)),
+                }
+            } else {
+                ctx.log.log(format!("not optimizing {:?}: only png outputs can be optimized",step.output_path));
+            }
+        }
+        check_budget(ctx, &step.output_path, size_kb, max_kb);
+    }
+    Ok(())
+}
 
-    let _ = Command::new(drawio_path.clone()).arg("--version").output().whatever_context::<&str, AppError>("Failed to locate drawio binary. Please specify path")?;
+/// Warn about and record `output_path` if its `size_kb` exceeds `max_kb`
+fn check_budget(ctx: &BuildContext, output_path: &Path, size_kb: u64, max_kb: u64) {
+    if size_kb > max_kb {
+        let warning = format!("{:?} has {} KB and exceeds its budget of {} KB",output_path,size_kb,max_kb);
+        ctx.log.log(&warning);
+        ctx.progress.warn(&warning);
+        ctx.offenders.record(output_path, size_kb, max_kb);
+    }
+}
 
-    create_dir_all(&args.output).whatever_context::<std::string::String, AppError>(format!("Failed to create output dir at {}", &args.output))?;
+fn run_command(ctx: &BuildContext, file: &Path, config: &BuildConfig) -> Result<(),DrawioError> {
+    //the freshness check has to happen under the lock, to see the outputs of a job that wrote them concurrently
+    let _guard = ctx.locks.lock((0..layer_steps(&config.layer_config).len()).map(|idx| output_path(file, config, ctx.out_dir, idx)).collect());
+    let state_key = state_key(file, config);
+    let fingerprint = state::fingerprint(config, ctx.backend);
+    let sources = ctx.dependencies.sources(file);
+    let input_hash = state::hash_files(&sources).map_err(|e| staging_error(file, file, format!("failed to read drawio file or its dependencies : {:?}",e)))?;
+    for source in sources.iter().filter(|v| state::modified_in_future(v)) {
+        let warning = format!("{:?} has a modification time in the future. Comparing its content to the last build instead",source);
+        ctx.log.log(&warning);
+        ctx.progress.warn(&warning);
+    }
+    let freshness = ctx.state.freshness(&state_key, &fingerprint, &sources, &input_hash);
+    let mut steps = plan_export_steps(file, &sources, config, ctx.out_dir, &freshness);
+    let resume_status: Vec<JournalStatus> = match ctx.journal.resuming() {
+        true => (0..layer_steps(&config.layer_config).len()).map(|idx| ctx.journal.status(&output_path(file, config, ctx.out_dir, idx), &input_hash, &fingerprint)).collect(),
+        false => Vec::new(),
+    };
+    let resumed = |idx: usize| resume_status.get(idx).unwrap_or(&JournalStatus::Unknown);
+    if ctx.journal.resuming() {
+        //an interrupted step may have left a half written output that looks up to date
+        let all_steps = plan_export_steps(file, &sources, config, ctx.out_dir, &Freshness::Rebuild("interrupted"));
+        steps = all_steps.into_iter().filter(|step| match resumed(step.idx) {
+            JournalStatus::Completed => false,
+            JournalStatus::Interrupted => true,
+            JournalStatus::Unknown => steps.iter().any(|v| v.idx == step.idx),
+        }).collect();
+    }
+    //up-to-date steps are not built, but still count towards the total
+    for idx in 0..layer_steps(&config.layer_config).len() {
+        if !steps.iter().any(|s| s.idx == idx) {
+            let output_path = output_path(file, config, ctx.out_dir, idx);
+            let reason = match (resumed(idx),&freshness) {
+                (JournalStatus::Completed,_) => "completed before the build was interrupted",
+                (_,Freshness::Exists) => "source content did not change",
+                _ => "modified after source",
+            };
+            ctx.log.log(format!("skipping {:?}: up to date, {} and built with the same settings",output_path,reason));
+            if let Some(max_kb) = config.max_output_kb {
+                check_budget(ctx, &output_path, budget::size_kb(&output_path), max_kb);
+            }
+            ctx.report.record(file, &output_path, StepStatus::UpToDate);
+            ctx.progress.step_done(&output_path, "up to date");
+        }
+    }
+    for step in &steps {
+        let reason = match (step.old_modified_time,&freshness) {
+            (None,_) => "output does not exist",
+            (Some(_),_) if *resumed(step.idx) == JournalStatus::Interrupted => "started but not completed before the build was interrupted",
+            (Some(_),Freshness::Rebuild(reason)) => reason,
+            (Some(_),_) if sources.len() > 1 => "source or one of its dependencies modified after output",
+            (Some(_),_) => "source modified after output",
+        };
+        ctx.log.log(format!("building {:?} with layers {:?}: {}",step.output_path,step.layers,reason));
+    }
+    let journal = ctx.journal.file(&input_hash, &fingerprint);
+    if !steps.is_empty() && ctx.backend == Backend::Mock {
+        run_mock(ctx, file, config, &steps, &journal).inspect_err(|e| {
+            ctx.log.log(format!("failed to build {:?}: {}",file,e));
+            ctx.report.record_failure(file, &[e.output_path.as_path()], e);
+        })?;
+        for step in &steps {
+            ctx.report.record(file, &step.output_path, StepStatus::Built);
+            ctx.progress.step_done(&step.output_path, "built placeholder");
+        }
+    } else if !steps.is_empty() {
+        let result = export_steps(ctx, file, config, &steps, &journal).and_then(|_| enforce_budget(ctx, file, config, &steps, &journal));
+        if let Err(e) = &result {
+            ctx.log.log(format!("failed to build {:?}: {}",file,e));
+            let output_paths: Vec<&Path> = steps.iter().map(|s| s.output_path.as_path()).collect();
+            ctx.report.record_failure(file, &output_paths, e);
+        }
+        result?;
+        for step in &steps {
+            ctx.report.record(file, &step.output_path, StepStatus::Built);
+            ctx.progress.step_done(&step.output_path, "built");
+        }
+    }
+    if ctx.alt_text {
+        write_alt_texts(file, config, ctx.out_dir, &steps)?;
+    }
+    if !steps.is_empty() {
+        ctx.journal.sync().map_err(|e| journal_error(file, file, e))?;
+    }
+    ctx.state.record(&state_key, fingerprint, input_hash);
+    Ok(())
+}
 
+/// Returns `input` if it is a file, or all .drawio files in the folder `input`
+fn find_drawio_files(input: &str) -> Result<Vec<PathBuf>,AppError> {
+    if Path::new(input).is_file() {
+        return Ok(vec![PathBuf::from(input)]);
+    }
     let mut drawio_files = Vec::new();
-    let layer_re = Regex::new(r#"<mxCell id=".*" value=".*" parent="." />"#).whatever_context::<std::string::String, AppError>("failed to compile layer extraction regexp".to_string())?;
-    for dir_entry in fs::read_dir(&args.input).whatever_context::<std::string::String, AppError>(format!("error listing files in folder {}", &args.input))? {
+    for dir_entry in fs::read_dir(input).whatever_context::<std::string::String, AppError>(format!("error listing files in folder {}", input))? {
         let dir_entry = dir_entry.whatever_context::<std::string::String, AppError>("".to_string())?;
         if !dir_entry.path().is_file() {
             continue;
@@ -304,55 +1061,381 @@ fn main() -> Result<(), AppError> {
             },
             None => continue,
         }
+        drawio_files.push(dir_entry.path());
+    }
+    drawio_files.sort();
+    Ok(drawio_files)
+}
 
+/// Input files of a build in `input`, honoring the discovery options "--recursive" and "--respect-ignore-files"
+fn input_files(input: &str, recursive: bool, respect_ignore_files: bool) -> Result<Vec<PathBuf>,AppError> {
+    match recursive || respect_ignore_files {
+        true => discover::walk_drawio_files(Path::new(input), recursive, respect_ignore_files),
+        false => find_drawio_files(input),
+    }
+}
+
+/// Human readable name of the figure `config` builds from `file`, e.g. for error messages
+fn describe_figure(file: &Path, config: &BuildConfig) -> String {
+    match &config.page {
+        Some(page) => format!("{:?} page {:?}",file,page),
+        None => format!("{:?}",file),
+    }
+}
 
-        let content = fs::read_to_string(&dir_entry.path()).whatever_context::<std::string::String, AppError>(format!("failed to read file {:?}", &dir_entry.path()))?;
+/// Remove planned builds of a file that is planned under another path as well.
+/// Fails if different files or pages would write the same output, e.g. files with the same name in different folders
+/// or pages with the same name in an "--all-pages" build, instead of silently dropping one of them
+fn dedupe_outputs(planned: Vec<(PathBuf,BuildConfig)>, out_dir: &str) -> Result<Vec<(PathBuf,BuildConfig)>,AppError> {
+    let mut owners: HashMap<PathBuf,(PathBuf,BuildConfig)> = HashMap::new();
+    let mut remaining = Vec::new();
+    let mut collisions = Vec::new();
+    for (file,config) in planned {
+        let outputs: Vec<PathBuf> = (0..layer_steps(&config.layer_config).len()).map(|idx| output_path(&file, &config, out_dir, idx)).collect();
+        if let Some((output,(owner,owner_config))) = outputs.iter().find_map(|v| Some((v,owners.get(v)?))) {
+            let same_file = matches!((owner.canonicalize(),file.canonicalize()), (Ok(a),Ok(b)) if a == b);
+            if !same_file || owner_config.page != config.page {
+                collisions.push(format!("{} and {} both write {:?}",describe_figure(owner, owner_config),describe_figure(&file, &config),output));
+            }
+            continue;
+        }
+        owners.extend(outputs.into_iter().map(|v| (v,(file.clone(),config.clone()))));
+        remaining.push((file,config));
+    }
+    if !collisions.is_empty() {
+        whatever!("Several figures would be written to the same output. Rename the files or pages:\n{}",collisions.join("\n"));
+    }
+    Ok(remaining)
+}
 
-        let layer_count = match layer_re.find_iter(&content).count() {
-            0 => 1,
-            v => v,
+/// Determine the BuildConfig for each of the `files`.
+/// If `all_pages` is set, files with several pages get one BuildConfig per page.
+/// Additionally, each file (or page) gets one BuildConfig per locale in `translations`.
+/// Placeholders without a value are passed to `warn`
+fn plan_build(files: Vec<PathBuf>, config: &DrawioConfig, drawio_flags: &[String], all_pages: bool, translations: &Translations, warn: &mut dyn FnMut(String)) -> Result<Vec<(PathBuf,BuildConfig)>,AppError> {
+    //Later we need to quickly check if there is a config override for a given file
+    let mut file_to_config :HashMap<String, &DrawioFileConfig> = HashMap::new();
+    if let Some(overrides) = &config.inidividual_configs {
+        for x in overrides {
+            file_to_config.insert(x.name.clone(), x);
+        }
+    }
+
+    let global_env = config.env.clone().unwrap_or_default();
+    let global_variables = config.variables.clone().unwrap_or_default();
+    let mut drawio_files = Vec::new();
+    for input_path in files {
+        let file_name = input_path.file_name().unwrap_or_else(|| panic!("unexpected malformed path {:?}. Should no longer happen at this stage",input_path)).to_str().unwrap().to_string();
+
+        let mut config = BuildConfig{
+            flags: drawio_flags.to_vec(),
+            env: global_env.clone(),
+            layer_config: LayerConfig::Incremental(1),
+            variables: BTreeMap::new(),
+            locale: None,
+            page: None,
+            max_output_kb: config.max_output_kb,
         };
+        let mut variables = global_variables.clone();
+        if let Some(custom_config) = file_to_config.get(&file_name) {
+            if let Some(env) = &custom_config.env {
+                config.env.extend(env.clone());
+            }
+            if let Some(custom_variables) = &custom_config.variables {
+                variables.extend(custom_variables.clone());
+            }
+            if let Some(max_output_kb) = custom_config.max_output_kb {
+                config.max_output_kb = Some(max_output_kb);
+            }
+            if let Some(order) = &custom_config.order {
+                config.layer_config = LayerConfig::Custom(order.clone());
+            }
+        }
 
-        drawio_files.push((dir_entry.path(),layer_count));
+        let document = DrawioDocument::load(&input_path).whatever_context::<std::string::String, AppError>(format!("failed to parse file {:?}", &input_path))?;
+        //translations may introduce additional placeholders
+        let mut placeholders = document.placeholders();
+        for locale_translations in translations.values() {
+            let mut translated = document.clone();
+            translated.translate(locale_translations);
+            placeholders.extend(translated.placeholders());
+        }
+        for name in placeholders {
+            match variables.get(&name).cloned().or_else(|| env::var(&name).ok()) {
+                Some(value) => { config.variables.insert(name, value); },
+                None => warn(format!("no value for placeholder {{{{{}}}}} in {:?}. It is exported as is",name,input_path)),
+            }
+        }
 
-        
+        let incremental = matches!(config.layer_config, LayerConfig::Incremental(_));
+        let pages = document.pages();
+        let mut page_configs = Vec::new();
+        if all_pages && pages.len() > 1 {
+            for (idx,page) in pages.iter().enumerate() {
+                page_configs.push(BuildConfig{
+                    flags: with_page(&config.flags, idx),
+                    page: Some(page.name().to_string()),
+                    ..config.clone()
+                });
+            }
+        } else {
+            page_configs.push(config);
+        }
+        for mut config in page_configs {
+            if incremental {
+                let layer_count = pages.get(exported_page(&config.flags)).map(|page| page.layers().len()).unwrap_or_default();
+                config.layer_config = LayerConfig::Incremental(layer_count.max(1));
+            }
+            for (locale,locale_translations) in translations {
+                let locale_config = BuildConfig{
+                    locale: Some((locale.clone(),locale_translations.clone())),
+                    ..config.clone()
+                };
+                drawio_files.push((input_path.clone(),locale_config));
+            }
+            drawio_files.push((input_path.clone(),config));
+        }
     }
+    Ok(drawio_files)
+}
 
-    let task_count :usize = drawio_files.iter().map(|(_,steps)| *steps).sum();
-    let progress_bar = ProgressBar::new(task_count as u64);
-    progress_bar.set_style(ProgressStyle::with_template("[{elapsed}] {wide_bar} {pos:>7}/{len:7} {msg}").expect("progress bar template failed"));
-    progress_bar.enable_steady_tick(Duration::from_millis(200));
-    progress_bar.inc(0);
-    let first_err = drawio_files.par_iter().try_for_each(|(input_path,layer_count)| {
-        let file_name = input_path.file_name().expect(&format!("unexpected malformed path {:?}. Should no longer happen at this stage",input_path)).to_str().unwrap().to_string();
+fn main() -> Result<(), AppError> {
 
-        let config = match file_to_config.get(&file_name) {
-            Some(custom_config) => {
-                BuildConfig{
-                    flags: drawio_flags.clone(),
-                    layer_config: LayerConfig::Custom(custom_config.order.clone()),
-                }
-            },
-            None => BuildConfig{
-                flags: drawio_flags.clone(),
-                layer_config: LayerConfig::Incremental(*layer_count),
+    let args = Args::parse();
+    match &args.command {
+        Some(Commands::ExtractText(extract_args)) => extract_text::run(extract_args),
+        Some(Commands::Lint(lint_args)) => lint::run(lint_args),
+        Some(Commands::Fonts(fonts_args)) => fonts::run(fonts_args),
+        Some(Commands::Stats(stats_args)) => stats::run(stats_args),
+        Some(Commands::Diff(diff_args)) => diff::run(diff_args),
+        Some(Commands::Hook(hook_args)) => hook::run(hook_args),
+        Some(Commands::Decompress(decompress_args)) => decompress::run(decompress_args),
+        Some(Commands::Fmt(fmt_args)) => fmt::run(fmt_args),
+        Some(Commands::Merge(merge_args)) => merge::run(merge_args),
+        Some(Commands::Split(split_args)) => split::run(split_args),
+        Some(Commands::Bench(bench_args)) => bench::run(bench_args),
+        Some(Commands::Audit(audit_args)) => audit::run(audit_args),
+        None if archive::is_archive(&args.build.input) => {
+            let unpacked = archive::UnpackedArchive::unpack(Path::new(&args.build.input))?;
+            build(&args.build, unpacked.drawio_files(args.build.recursive, args.build.respect_ignore_files)?).map(|_| ())
+        },
+        None => build(&args.build, input_files(&args.build.input, args.build.recursive, args.build.respect_ignore_files)?).map(|_| ()),
+    }
+}
+
+/// Flags passed to drawio for all export steps
+fn drawio_flags(args: &BuildArgs) -> Result<Vec<String>, AppError> {
+    let  mut drawio_flags : Vec<String> = args.build_args.split(" ").map(|v| v.to_string()).collect();
+
+    //If draft mode, change scale to 1
+    if args.draft {
+        let mut scale_flag_idx = None;
+        for (i,v) in drawio_flags.iter().enumerate() {
+            if v == "-s" || v == "--scale" {
+                scale_flag_idx = Some(i);
+                break;
+            }
+        }
+        if let Some(idx) = scale_flag_idx {
+            if drawio_flags.len() < idx + 1 {
+                whatever!("Scale flag does not have argument");
+            }
+            drawio_flags[idx+1] = "1".to_string();
+        }
+    }
+    Ok(drawio_flags)
+}
+
+/// Build `files`. Returns the BuildConfig of each file
+fn build(args: &BuildArgs, files: Vec<PathBuf>) -> Result<Vec<(PathBuf,BuildConfig)>, AppError> {
+
+    let drawio_flags = drawio_flags(args)?;
+    let config = load_config(args.config.as_deref())?;
+
+    let drawio_path = match &args.drawio {
+        Some(v) => v.clone(),
+        None => "drawio".to_string(),
+    };
+
+    create_dir_all(&args.output).whatever_context::<std::string::String, AppError>(format!("Failed to create output dir at {}", &args.output))?;
+
+    let recommended_jobs = args.jobs.is_none().then(bench::recommended_jobs).flatten();
+    if let Some((jobs,path)) = &recommended_jobs {
+        //the recommendation applies to all projects of the user, so make it visible where it comes from
+        eprintln!("Running {} jobs in parallel as recommended by \"bench\" in {}. Pass --jobs to override",jobs,path.display());
+    }
+    if let Some(jobs) = args.jobs.or(recommended_jobs.as_ref().map(|(v,_)| *v)) {
+        //fails if the pool is already running, e.g. when the hook builds twice, which keeps the first value
+        let _ = rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global();
+    }
+    if args.cpus_per_job.is_some() && !priority::AFFINITY_SUPPORTED {
+        eprintln!("Warning: --cpus-per-job is not supported on this platform and is ignored");
+    }
+    let translations = load_translations(args.locales.as_deref())?;
+    let log = RunLog::create(args.log_file.as_deref().map(Path::new)).whatever_context::<String,AppError>(format!("Failed to create log file {:?}", &args.log_file))?;
+    log.log(format!("drawio-builder {} : {}",env!("CARGO_PKG_VERSION"),env::args().collect::<Vec<_>>().join(" ")));
+    log.log(format!("found {} input files in {} : {:?}",files.len(),args.input,files));
+    match &recommended_jobs {
+        Some((_,path)) => log.log(format!("running {} jobs in parallel, recommended by bench in {:?}",rayon::current_num_threads(),path)),
+        _ => log.log(format!("running {} jobs in parallel",rayon::current_num_threads())),
+    }
+    //the progress bar needs the planned files, so the warnings of planning are reported once it exists
+    let mut plan_warnings = Vec::new();
+    let drawio_files = dedupe_outputs(plan_build(files, &config, &drawio_flags, args.all_pages, &translations, &mut |v| plan_warnings.push(v))?, &args.output)?;
+    for (file,config) in &drawio_files {
+        log.log(format!("planned {:?}{}{} : layer steps {:?}, flags {:?}, placeholders {:?}",
+            file,
+            config.page.as_ref().map(|v| format!(" page {:?}",v)).unwrap_or_default(),
+            config.locale.as_ref().map(|(v,_)| format!(" locale {}",v)).unwrap_or_default(),
+            layer_steps(&config.layer_config),config.flags,config.variables));
+    }
+
+    let task_count :usize = drawio_files.iter().map(|(_,config)| layer_steps(&config.layer_config).len()).sum();
+    let progress = Progress::new(args.progress, task_count as u64);
+    for warning in &plan_warnings {
+        log.log(warning);
+        progress.warn(warning);
+    }
+    let state = BuildState::load(Path::new(&args.output));
+    let report = BuildReport::default();
+    let locks = OutputLocks::default();
+    let warmup = OnceLock::new();
+    let unique_files: Vec<PathBuf> = drawio_files.iter().map(|(file,_)| file.clone()).collect::<std::collections::BTreeSet<_>>().into_iter().collect();
+    let dependencies = Dependencies::resolve(&unique_files);
+    for file in &unique_files {
+        if !dependencies.of(file).is_empty() {
+            log.log(format!("{:?} depends on {:?}",file,dependencies.of(file)));
+        }
+    }
+    let mut workers = match &args.ssh_workers {
+        Some(path) => WorkerPool::load(Path::new(path), Path::new(&args.output))?,
+        None => WorkerPool::local(),
+    };
+    if args.backend == Backend::Drawio {
+        let located = Command::new(drawio_path.clone()).arg("--version").output();
+        match located {
+            Err(_) if workers.has_remotes() => {
+                eprintln!("Warning: drawio binary {} not found, exporting on the SSH workers only",drawio_path);
+                workers.disable_local();
             },
-        };
-        run_command(&drawio_path,input_path, &config,&args.output,&progress_bar)
+            Err(e) => return Err(e).whatever_context::<&str, AppError>("Failed to locate drawio binary. Please specify path"),
+            Ok(_) => (),
+        }
+    }
+    if workers.has_remotes() {
+        log.log(format!("running {} jobs in parallel on the local machine and the SSH workers",workers.total_jobs()));
+    }
+    let journal = Journal::open(Path::new(&args.output), args.resume).whatever_context::<String,AppError>(format!("Failed to open build journal in {}", &args.output))?;
+    if args.resume && !journal.resuming() {
+        eprintln!("Warning: no interrupted build to resume in {}, building as usual",&args.output);
+    } else if journal.resuming() {
+        log.log("resuming interrupted build");
+    }
+    let offenders = Offenders::default();
+    let allowed_cpus = priority::allowed_cpus();
+    if args.cpus_per_job.is_some() {
+        log.log(format!("pinning jobs to the allowed CPUs {:?}",allowed_cpus));
+    }
+    let ctx = BuildContext {
+        drawio_binary: &drawio_path,
+        electron_args: &args.electron_args,
+        out_dir: &args.output,
+        batch: args.batch,
+        alt_text: args.alt_text,
+        nice: args.nice,
+        cpus_per_job: args.cpus_per_job,
+        allowed_cpus: &allowed_cpus,
+        pin_warned: &AtomicBool::new(false),
+        progress: &progress,
+        state: &state,
+        report: &report,
+        locks: &locks,
+        log: &log,
+        warmup: &warmup,
+        workers: &workers,
+        dependencies: &dependencies,
+        journal: &journal,
+        oversize: args.oversize,
+        offenders: &offenders,
+        backend: args.backend,
+    };
+    let build_all = || drawio_files.par_iter().try_for_each(|(input_path,config)| {
+        run_command(&ctx, input_path, config)
     });
+    //remote exports mostly wait for ssh, so they get build threads of their own instead of taking those of the local jobs
+    let first_err = match workers.has_remotes() {
+        true => rayon::ThreadPoolBuilder::new().num_threads(workers.total_jobs()).build()
+            .whatever_context::<&str,AppError>("Failed to start build threads")?
+            .install(build_all),
+        false => build_all(),
+    };
+    let _ = fs::remove_dir_all(staging_root());
+    state.save().whatever_context::<String,AppError>(format!("Failed to save build state to {:?}", &args.output))?;
+    if workers.has_remotes() {
+        workers.save(Path::new(&args.output)).whatever_context::<String,AppError>(format!("Failed to save worker speeds to {:?}", &args.output))?;
+    }
+    let results = report.finish(&drawio_files, &args.output);
+    let offenders = offenders.finish();
+    if !offenders.is_empty() {
+        eprintln!("{} output(s) exceed their size budget:",offenders.len());
+        for offender in &offenders {
+            eprintln!("  {} : {} KB, budget {} KB",offender.output_path.display(),offender.size_kb,offender.max_kb);
+        }
+    }
+    if let Some(junit_path) = &args.junit {
+        report::write_junit(&results, Path::new(junit_path)).whatever_context::<String,AppError>(format!("Failed to write JUnit report to {}", junit_path))?;
+    }
+    //a broken drawio installation fails every figure, so report the cause once instead
+    if let Some(Some(e)) = warmup.get() {
+        log.log(format!("drawio warmup failed : {}",e));
+        let mut message = format!("drawio failed to export a minimal diagram, no figure has been built. Command: {}",
+            e.command.as_ref().map(|v| v.argv.join(" ")).unwrap_or_default());
+        if let Some(exit_code) = e.exit_code {
+            message += &format!("\nExit status: {}",exit_code);
+        }
+        for (name,output) in [("stdout",&e.stdout),("stderr",&e.stderr)] {
+            if !output.trim_ascii().is_empty() {
+                message += &format!("\n{}:\n{}",name,String::from_utf8_lossy(output).trim_end());
+            }
+        }
+        whatever!("{}",message);
+    }
     match first_err {
-        Ok(_) => progress_bar.finish_with_message("Build all figures"),
+        Ok(_) => {
+            log.log("build finished");
+            //error logs of an earlier build would suggest that something is still broken
+            for name in ["drawio-builder-errors.log","drawio-builder-errors.json"] {
+                let path = Path::new(&args.output).join(name);
+                if path.exists() {
+                    fs::remove_file(&path).whatever_context::<String,AppError>(format!("Failed to remove stale error log {:?}",path))?;
+                }
+            }
+            journal.remove().whatever_context::<String,AppError>(format!("Failed to remove build journal in {}", &args.output))?;
+            if args.gallery {
+                write_gallery(&drawio_files, &args.output)?;
+            }
+            progress.finish("Build all figures");
+        },
         Err(e) => {
+            log.log(format!("build failed : {}",e));
             let log_path = PathBuf::from(&args.output).join("drawio-builder-errors.log");
             let mut log_file = File::create(&log_path).whatever_context::<String,AppError>(format!("At least one figure failed to build and we failed to create the error log at {:?}",log_path))?;
             write!(log_file,"Stderr and Stdout when trying to create {:?}\n\n",&e.output_path).whatever_context::<&str,AppError>("Failed to write failed figure's build to log file")?;
             log_file.write_all(&e.stdout).whatever_context::<&str,AppError>("Failed to write stdout of failed figure's build to log file")?;
             log_file.write_all(&e.stderr).whatever_context::<&str,AppError>("Failed to write stderr or failed figure's build to log file")?;
-            whatever!("At least one figure failed to build. Error log has been created at {:?}",&log_path);
+            if !e.crash_artifacts.is_empty() {
+                write!(log_file,"\n\nDrawio crashed. Crash dumps and logs:\n").whatever_context::<&str,AppError>("Failed to write crash artifacts to log file")?;
+                for artifact in &e.crash_artifacts {
+                    writeln!(log_file,"{}",artifact.display()).whatever_context::<&str,AppError>("Failed to write crash artifacts to log file")?;
+                }
+            }
+            let json_log_path = PathBuf::from(&args.output).join("drawio-builder-errors.json");
+            report::write_error_log(&results, &json_log_path).whatever_context::<String,AppError>(format!("Failed to write JSON error log to {:?}",json_log_path))?;
+            whatever!("At least one figure failed to build. Error logs have been created at {:?} and {:?}",&log_path,&json_log_path);
         },
     }
 
-    Ok(())
+    Ok(drawio_files)
 }
 
 
@@ -361,6 +1444,13 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_ssh_workers_require_batch() {
+        //remote workers only export batches, so they would silently be ignored with per step exports
+        assert!(Args::try_parse_from(["drawio-builder", "-i", "src", "--ssh-workers", "workers.json"]).is_err());
+        assert!(Args::try_parse_from(["drawio-builder", "-i", "src", "--ssh-workers", "workers.json", "--batch"]).is_ok());
+    }
+
     //fn assemble_layer_flag(config: LayerConfig) -> Vec<String> {
 
     #[test]
@@ -379,4 +1469,78 @@ mod test {
         let got = assemble_layer_cli_flag(&LayerConfig::Custom(vec![vec![1,0],vec![2,5]]));
         assert_eq!(want,got);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name(" Overview: a/b v1.2 "),"Overview_ a_b v1.2");
+        assert_eq!(sanitize_file_name("my figure"),"my figure");
+        assert_eq!(sanitize_file_name("Übersicht-1"),"Übersicht-1");
+    }
+
+    #[test]
+    fn test_dedupe_outputs() {
+        let config = || BuildConfig{
+            flags: Vec::new(),
+            env: BTreeMap::new(),
+            layer_config: LayerConfig::Incremental(2),
+            variables: BTreeMap::new(),
+            locale: None,
+            page: None,
+            max_output_kb: None,
+        };
+        let planned = vec![
+            (PathBuf::from("test-data/cvm-motivation.drawio"),config()),
+            (PathBuf::from("test-data/./cvm-motivation.drawio"),config()),
+            (PathBuf::from("test-data/lint-problems.drawio"),config()),
+        ];
+        let remaining = dedupe_outputs(planned, "out").unwrap();
+        let files: Vec<&Path> = remaining.iter().map(|(file,_)| file.as_path()).collect();
+        assert_eq!(files,[Path::new("test-data/cvm-motivation.drawio"),Path::new("test-data/lint-problems.drawio")]);
+        let planned = vec![(PathBuf::from("a/fig.drawio"),config()),(PathBuf::from("b/fig.drawio"),config())];
+        assert!(dedupe_outputs(planned, "out").is_err());
+    }
+
+    #[test]
+    fn test_all_pages_collision() {
+        let dir = env::temp_dir().join(format!("drawio-builder-pages-{}",std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let page = |name: &str| format!(r#"<diagram id="{0}" name="{0}"><mxGraphModel><root><mxCell id="0"/><mxCell id="1" parent="0"/></root></mxGraphModel></diagram>"#,name);
+        let files = [dir.join("a.drawio"),dir.join("b.drawio")];
+        for file in &files {
+            fs::write(file, format!("<mxfile>{}{}</mxfile>",page("Page-1"),page("Page-2"))).unwrap();
+        }
+        let planned = plan_build(files.to_vec(), &DrawioConfig::default(), &[], true, &Translations::new(), &mut |_| ()).unwrap();
+        let Err(err) = dedupe_outputs(planned, "out") else {
+            panic!("colliding pages were not rejected");
+        };
+        let err = err.to_string();
+        assert!(err.contains("page \"Page-1\""),"{}",err);
+        //uniquely named pages are fine
+        fs::write(&files[1], format!("<mxfile>{}{}</mxfile>",page("Overview"),page("Details"))).unwrap();
+        let planned = plan_build(files.to_vec(), &DrawioConfig::default(), &[], true, &Translations::new(), &mut |_| ()).unwrap();
+        assert_eq!(dedupe_outputs(planned, "out").unwrap().len(),4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_all_pages_replaces_page_flag() {
+        let dir = env::temp_dir().join(format!("drawio-builder-page-flag-{}",std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let page = |name: &str, layers: usize| {
+            let layers: String = (0..layers).map(|idx| format!(r#"<mxCell id="l{}" parent="0"/>"#,idx)).collect();
+            format!(r#"<diagram id="{0}" name="{0}"><mxGraphModel><root><mxCell id="0"/>{1}</root></mxGraphModel></diagram>"#,name,layers)
+        };
+        let file = dir.join("a.drawio");
+        fs::write(&file, format!("<mxfile>{}{}</mxfile>",page("Overview",1),page("Details",3))).unwrap();
+        let flags = ["-p","0","--transparent"].map(String::from);
+        let planned = plan_build(vec![file], &DrawioConfig::default(), &flags, true, &Translations::new(), &mut |_| ()).unwrap();
+        let pages: Vec<(Vec<String>,usize)> = planned.iter().map(|(_,config)| (config.flags.clone(),exported_page(&config.flags))).collect();
+        assert_eq!(pages,[
+            (["--transparent","-p","0"].map(String::from).to_vec(),0),
+            (["--transparent","-p","1"].map(String::from).to_vec(),1),
+        ]);
+        assert!(matches!(planned[1].1.layer_config,LayerConfig::Incremental(3)));
+        assert_eq!(dedupe_outputs(planned, "out").unwrap().len(),2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+//! `merge` subcommand: combine several .drawio files into a single multi-page file
+
+use clap::Args;
+use snafu::prelude::*;
+use std::path::PathBuf;
+
+use crate::diagram::DrawioDocument;
+use crate::{find_drawio_files, AppError};
+
+#[derive(Args)]
+pub struct MergeArgs {
+    ///.drawio files or folders with .drawio files. Pages are added in the given order
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    ///Path of the merged .drawio file
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+pub fn run(args: &MergeArgs) -> Result<(), AppError> {
+    let mut merged = DrawioDocument::new();
+    for input in &args.inputs {
+        for file in find_drawio_files(input)? {
+            //re-running the command on the same folder must not merge the previous result
+            if file.canonicalize().ok() == args.output.canonicalize().ok() {
+                continue;
+            }
+            let document = DrawioDocument::load(&file)
+                .whatever_context::<String, AppError>(format!("failed to parse file {:?}", &file))?;
+            let file_stem = file.file_stem().unwrap().to_str().unwrap();
+            let pages = document.pages();
+            //The first page is named after its source file, so that "--all-pages" builds produce the same outputs
+            // as building the source file. Drawio only exports the first page by default
+            for (idx, page) in pages.iter().enumerate() {
+                let name = match idx {
+                    0 => file_stem.to_string(),
+                    _ => format!("{}-{}", file_stem, page.name()),
+                };
+                merged.push_page(page, &name);
+            }
+            println!("{} -> {} ({} pages)", file.display(), args.output.display(), pages.len());
+        }
+    }
+
+    merged
+        .save(&args.output)
+        .whatever_context::<String, AppError>(format!("failed to write {:?}", &args.output))?;
+    Ok(())
+}
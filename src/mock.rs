@@ -0,0 +1,118 @@
+//! Mock export backend, which writes placeholder images instead of running drawio. It lets projects develop and
+//! test the naming of outputs and their LaTeX integration on machines and CI runners without drawio installed
+
+use clap::ValueEnum;
+use image::{ImageFormat, Rgb, RgbImage};
+use std::path::Path;
+
+use crate::diagram::{DrawioDocument, Geometry, Page};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Backend {
+    ///Export with the drawio desktop app
+    Drawio,
+    ///Write placeholder images with the size drawio would export, without running drawio
+    Mock,
+}
+
+/// Size of placeholders for empty pages, in pixels at scale 1
+const EMPTY_PAGE_SIZE: f64 = 100.0;
+
+/// Largest placeholder width or height, to keep absurd geometries from exhausting memory
+const MAX_SIZE: f64 = 16384.0;
+
+/// Numeric value of the first of the drawio flags `names` in `flags`
+pub fn flag_value(flags: &[String], names: &[&str]) -> Option<f64> {
+    let idx = flags.iter().position(|v| names.contains(&v.as_str()))?;
+    flags.get(idx + 1)?.parse().ok()
+}
+
+/// Bounding box of the visible vertices on `layers`, i.e. the area of a cropped export
+pub fn bounds(page: &Page, layers: &[usize]) -> Option<Geometry> {
+    page.cells()
+        .iter()
+        .filter(|c| c.is_vertex() && page.is_visible(c))
+        .filter(|c| page.layer_index(c).is_some_and(|idx| layers.contains(&idx)))
+        .filter_map(|c| page.absolute_geometry(c))
+        .reduce(|a, b| {
+            let (x, y) = (a.x.min(b.x), a.y.min(b.y));
+            Geometry {
+                x,
+                y,
+                width: (a.x + a.width).max(b.x + b.width) - x,
+                height: (a.y + a.height).max(b.y + b.height) - y,
+            }
+        })
+}
+
+/// Size in pixels of the image drawio exports for `layers` of `page` with the drawio `flags`,
+/// honoring the scale, border, width and height flags
+pub fn export_size(page: &Page, layers: &[usize], flags: &[String]) -> (u32, u32) {
+    let (mut width, mut height) = match bounds(page, layers) {
+        Some(bounds) if bounds.width > 0.0 && bounds.height > 0.0 => (bounds.width, bounds.height),
+        _ => (EMPTY_PAGE_SIZE, EMPTY_PAGE_SIZE),
+    };
+    //width and height fit the diagram into the given size and take precedence over the scale
+    let scale = match (flag_value(flags, &["--width"]), flag_value(flags, &["--height"])) {
+        (Some(w), Some(h)) => (w / width).min(h / height),
+        (Some(w), None) => w / width,
+        (None, Some(h)) => h / height,
+        (None, None) => flag_value(flags, &["-s", "--scale"]).unwrap_or(1.0),
+    };
+    let border = flag_value(flags, &["-b", "--border"]).unwrap_or(0.0);
+    width = width * scale + 2.0 * border;
+    height = height * scale + 2.0 * border;
+    let clamp = |v: f64| v.round().clamp(1.0, MAX_SIZE) as u32;
+    (clamp(width), clamp(height))
+}
+
+/// Write a placeholder for `layers` of page `page_idx` of `file` to `output_path`: a crossed out box
+pub fn export(
+    file: &Path,
+    page_idx: usize,
+    layers: &[usize],
+    flags: &[String],
+    output_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let document = DrawioDocument::load(file)?;
+    let pages = document.pages();
+    let page = pages.get(page_idx).ok_or(format!("file has no page with index {}", page_idx))?;
+    let (width, height) = export_size(page, layers, flags);
+    let line = Rgb([128, 128, 128]);
+    let image = RgbImage::from_fn(width, height, |x, y| {
+        let on_border = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+        //distance of the pixel from the two diagonals, scaled by the length of the longer side
+        let (x, y, w, h) = (x as i64, y as i64, width as i64 - 1, height as i64 - 1);
+        let on_diagonal = (x * h - y * w).abs() <= w.max(h) || (x * h - (h - y) * w).abs() <= w.max(h);
+        match on_border || on_diagonal {
+            true => line,
+            false => Rgb([240, 240, 240]),
+        }
+    });
+    image.save_with_format(output_path, ImageFormat::Png)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_size() {
+        let document = DrawioDocument::parse(
+            r#"<mxfile><diagram id="p" name="Page-1"><mxGraphModel><root>
+            <mxCell id="0"/><mxCell id="1" parent="0"/><mxCell id="2" parent="0"/>
+            <mxCell id="3" vertex="1" parent="1"><mxGeometry x="10" y="20" width="100" height="50" as="geometry"/></mxCell>
+            <mxCell id="4" vertex="1" parent="2"><mxGeometry x="200" y="20" width="10" height="10" as="geometry"/></mxCell>
+            </root></mxGraphModel></diagram></mxfile>"#
+                .as_bytes(),
+        )
+        .unwrap();
+        let pages = document.pages();
+        let flags = |v: &str| -> Vec<String> { v.split(' ').map(|v| v.to_string()).collect() };
+        assert_eq!(export_size(&pages[0], &[0], &flags("-x -f png -s 2")), (200, 100));
+        assert_eq!(export_size(&pages[0], &[0, 1], &flags("-x -f png -b 5")), (210, 60));
+        assert_eq!(export_size(&pages[0], &[0], &flags("-x -s 5 --width 50")), (50, 25));
+        assert_eq!(export_size(&pages[0], &[], &flags("-x")), (100, 100));
+    }
+}
@@ -0,0 +1,141 @@
+//! Reduced CPU and I/O priority and CPU affinity for the spawned drawio processes, so that long builds
+//! do not make the machine unusable. Electron's helper processes inherit both from the drawio process
+
+use std::process::Command;
+
+/// Niceness of the drawio processes on Unix
+#[cfg(unix)]
+const NICENESS: libc::c_int = 10;
+
+/// Start the process of `command` with low CPU and, on Linux, idle I/O priority
+#[cfg(unix)]
+pub fn lower_priority(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    //Safety: the closure runs between fork and exec and only performs async-signal-safe syscalls
+    unsafe {
+        command.pre_exec(|| {
+            //best effort, we rather export with normal priority than not at all
+            libc::setpriority(libc::PRIO_PROCESS, 0, NICENESS);
+            #[cfg(target_os = "linux")]
+            {
+                const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+                const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+                const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    0,
+                    IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+                );
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Start the process of `command` with below normal priority
+#[cfg(windows)]
+pub fn lower_priority(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+    const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+    command.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn lower_priority(_command: &mut Command) {}
+
+/// True if `pin_to_cpus` has an effect on this platform
+pub const AFFINITY_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// CPUs this process may run on, e.g. a subset of the machine's CPUs inside a container or on a pinned CI runner
+#[cfg(target_os = "linux")]
+pub fn allowed_cpus() -> Vec<usize> {
+    match affinity(0) {
+        Ok(cpus) if !cpus.is_empty() => cpus,
+        _ => all_cpus(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cpus() -> Vec<usize> {
+    all_cpus()
+}
+
+fn all_cpus() -> Vec<usize> {
+    (0..std::thread::available_parallelism().map(|v| v.get()).unwrap_or(1)).collect()
+}
+
+/// CPUs the process `pid` may run on. A `pid` of 0 refers to this process
+#[cfg(target_os = "linux")]
+pub fn affinity(pid: u32) -> std::io::Result<Vec<usize>> {
+    //Safety: cpu_set_t is a plain bit mask, for which all zeros is a valid value
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    //Safety: `set` is a valid cpu_set_t of the given size
+    if unsafe { libc::sched_getaffinity(pid as libc::pid_t, std::mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    //Safety: `set` has been initialized by sched_getaffinity
+    Ok((0..libc::CPU_SETSIZE as usize).filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) }).collect())
+}
+
+/// CPUs for the drawio processes of job `slot` if each job may use `cpus_per_job` of the `allowed` CPUs.
+/// Consecutive slots get disjoint CPUs, until we run out of CPUs and start over
+pub fn job_cpus(slot: usize, cpus_per_job: usize, allowed: &[usize]) -> Vec<usize> {
+    if allowed.is_empty() {
+        return Vec::new();
+    }
+    let count = cpus_per_job.clamp(1, allowed.len());
+    (0..count).map(|i| allowed[(slot * count + i) % allowed.len()]).collect()
+}
+
+/// Restrict the process of `command` and all of its threads to `cpus`
+#[cfg(target_os = "linux")]
+pub fn pin_to_cpus(command: &mut Command, cpus: &[usize]) {
+    use std::os::unix::process::CommandExt;
+    //Safety: cpu_set_t is a plain bit mask, for which all zeros is a valid value
+    let set = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus.iter().filter(|v| **v < libc::CPU_SETSIZE as usize) {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        set
+    };
+    //Safety: the closure runs between fork and exec and only performs async-signal-safe syscalls
+    unsafe {
+        command.pre_exec(move || {
+            //best effort, like the priority
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_to_cpus(_command: &mut Command, _cpus: &[usize]) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_job_cpus() {
+        let all: Vec<usize> = (0..8).collect();
+        assert_eq!(job_cpus(0, 2, &all), [0, 1]);
+        assert_eq!(job_cpus(1, 2, &all), [2, 3]);
+        //more jobs than CPUs wrap around
+        assert_eq!(job_cpus(4, 2, &all), [0, 1]);
+        assert_eq!(job_cpus(1, 3, &all[..4]), [3, 0, 1]);
+        assert_eq!(job_cpus(0, 16, &all[..4]), [0, 1, 2, 3]);
+        //inside a cpuset, only the allowed CPUs are used
+        assert_eq!(job_cpus(1, 2, &[4, 5, 6, 7]), [6, 7]);
+        assert_eq!(job_cpus(1, 1, &[]), Vec::<usize>::new());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_allowed_cpus() {
+        let allowed = allowed_cpus();
+        assert!(!allowed.is_empty());
+        assert_eq!(affinity(std::process::id()).unwrap(), allowed);
+    }
+}
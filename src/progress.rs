@@ -0,0 +1,73 @@
+//! Build progress, either as an interactive progress bar or as plain lines for CI logs
+
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ProgressMode {
+    ///Progress bar if stdout and stderr are terminals, plain lines otherwise
+    Auto,
+    Bar,
+    ///One line per finished export step, e.g. "[12/80] built out/arch-3.png"
+    Plain,
+}
+
+pub enum Progress {
+    Bar(ProgressBar),
+    Plain { total: u64, done: AtomicU64 },
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode, total: u64) -> Self {
+        let interactive = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+        match mode {
+            ProgressMode::Bar => Self::bar(total),
+            ProgressMode::Auto if interactive => Self::bar(total),
+            _ => Progress::Plain {
+                total,
+                done: AtomicU64::new(0),
+            },
+        }
+    }
+
+    fn bar(total: u64) -> Self {
+        let progress_bar = ProgressBar::new(total);
+        progress_bar.set_style(
+            ProgressStyle::with_template("[{elapsed}] {wide_bar} {pos:>7}/{len:7} {msg}")
+                .expect("progress bar template failed"),
+        );
+        progress_bar.enable_steady_tick(Duration::from_millis(200));
+        progress_bar.inc(0);
+        Progress::Bar(progress_bar)
+    }
+
+    /// Count the export step writing `output_path` as done. `status` describes what happened, e.g. "built"
+    pub fn step_done(&self, output_path: &Path, status: &str) {
+        match self {
+            Progress::Bar(progress_bar) => progress_bar.inc(1),
+            Progress::Plain { total, done } => {
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                println!("[{}/{}] {} {}", done, total, status, output_path.display());
+            }
+        }
+    }
+
+    /// Print `message` as a warning without garbling the progress bar
+    pub fn warn(&self, message: &str) {
+        match self {
+            Progress::Bar(progress_bar) => progress_bar.suspend(|| eprintln!("Warning: {}", message)),
+            Progress::Plain { .. } => eprintln!("Warning: {}", message),
+        }
+    }
+
+    pub fn finish(&self, message: &'static str) {
+        match self {
+            Progress::Bar(progress_bar) => progress_bar.finish_with_message(message),
+            Progress::Plain { .. } => println!("{}", message),
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Experimental distributed builds. Export steps of a file are exported either locally or on one of several
+//! SSH workers: the staged copies are copied to the worker, exported with the worker's drawio and copied back.
+//! The local scheduler assigns each file to the worker expected to finish it first, based on the speed of
+//! earlier builds, which is remembered in the output folder
+
+use serde::Deserialize;
+use snafu::prelude::*;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::{spawn_process, wait_drawio, AppError, BuildConfig, BuildContext, DrawioCommand, DrawioError};
+
+const SPEED_FILE_NAME: &str = "drawio-builder-workers.json";
+
+///Name of the local machine in the speed history
+const LOCAL: &str = "local";
+
+///Weight of the latest measurement in the speed estimate
+const SPEED_SMOOTHING: f64 = 0.3;
+
+#[derive(Deserialize)]
+pub struct RemoteWorker {
+    ///SSH destination, e.g. "user@build-1"
+    host: String,
+    ///drawio binary on the worker
+    #[serde(default = "default_drawio")]
+    drawio: String,
+    ///Number of files the worker exports in parallel
+    #[serde(default = "default_jobs")]
+    jobs: usize,
+}
+
+fn default_drawio() -> String {
+    "drawio".to_string()
+}
+
+fn default_jobs() -> usize {
+    1
+}
+
+struct WorkerLoad {
+    running: usize,
+    pending_steps: usize,
+    ///None until we have exported something with this worker
+    secs_per_step: Option<f64>,
+}
+
+/// Local machine and remote workers, with their current load and measured speed
+pub struct WorkerPool {
+    remotes: Vec<RemoteWorker>,
+    local_jobs: usize,
+    ///False if there is no local drawio, so that all files are exported on the remote workers
+    local: bool,
+    ///Index 0 is the local machine, index i+1 is `remotes[i]`
+    loads: Mutex<Vec<WorkerLoad>>,
+}
+
+/// A worker reserved for exporting the steps of one file. Frees the worker when dropped
+pub struct WorkerLease<'a> {
+    pool: &'a WorkerPool,
+    idx: usize,
+    steps: usize,
+    started: Instant,
+}
+
+impl WorkerPool {
+    /// Pool without remote workers, i.e. everything is exported locally
+    pub fn local() -> Self {
+        Self::new(Vec::new(), rayon::current_num_threads(), &BTreeMap::new())
+    }
+
+    /// Load the worker list from the JSON file `workers` and the speed history from `out_dir`
+    pub fn load(workers: &Path, out_dir: &Path) -> Result<Self, AppError> {
+        let file = File::open(workers).whatever_context::<String, AppError>(format!("failed to open worker list {:?}", workers))?;
+        let remotes: Vec<RemoteWorker> = serde_json::from_reader(BufReader::new(file))
+            .whatever_context::<String, AppError>(format!("failed to parse worker list {:?}", workers))?;
+        let history = File::open(out_dir.join(SPEED_FILE_NAME))
+            .ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+            .unwrap_or_default();
+        Ok(Self::new(remotes, rayon::current_num_threads(), &history))
+    }
+
+    fn new(remotes: Vec<RemoteWorker>, local_jobs: usize, history: &BTreeMap<String, f64>) -> Self {
+        let names = std::iter::once(LOCAL).chain(remotes.iter().map(|v| v.host.as_str()));
+        let loads = names
+            .map(|name| WorkerLoad {
+                running: 0,
+                pending_steps: 0,
+                secs_per_step: history.get(name).copied(),
+            })
+            .collect();
+        WorkerPool {
+            remotes,
+            local_jobs,
+            local: true,
+            loads: Mutex::new(loads),
+        }
+    }
+
+    pub fn has_remotes(&self) -> bool {
+        !self.remotes.is_empty()
+    }
+
+    /// Number of files exported in parallel on all workers together, i.e. the number of build threads that keep
+    /// all of them busy. Remote exports mostly wait for ssh, so they must not take slots of the local jobs
+    pub fn total_jobs(&self) -> usize {
+        let local_jobs = if self.local { self.local_jobs } else { 0 };
+        local_jobs + (1..=self.remotes.len()).map(|idx| self.jobs(idx)).sum::<usize>()
+    }
+
+    /// Export everything on the remote workers, e.g. because drawio is not installed locally
+    pub fn disable_local(&mut self) {
+        self.local = false;
+    }
+
+    fn jobs(&self, idx: usize) -> usize {
+        match idx {
+            0 => self.local_jobs,
+            _ => self.remotes[idx - 1].jobs.max(1),
+        }
+    }
+
+    /// Reserve the worker with a free slot that is expected to finish exporting `steps` more steps first.
+    /// With one build thread per slot of `total_jobs`, there always is a free slot
+    pub fn acquire(&self, steps: usize) -> WorkerLease<'_> {
+        let mut loads = self.loads.lock().unwrap();
+        //the steps queued on a worker are spread over its parallel jobs, but our steps run in a single one
+        let expected_finish = |idx: usize, load: &WorkerLoad| {
+            let queued = load.pending_steps as f64 / self.jobs(idx) as f64;
+            //workers without measurements are tried first, so that we learn their speed
+            (queued + steps as f64) * load.secs_per_step.unwrap_or(0.0)
+        };
+        let first_to_finish = |free_slot: bool| {
+            loads
+                .iter()
+                .enumerate()
+                .filter(|(idx, load)| (*idx != 0 || self.local) && (!free_slot || load.running < self.jobs(*idx)))
+                .min_by(|(a_idx, a), (b_idx, b)| expected_finish(*a_idx, a).total_cmp(&expected_finish(*b_idx, b)))
+                .map(|(idx, _)| idx)
+        };
+        //all slots are taken if more threads build than `total_jobs`
+        let idx = first_to_finish(true).or_else(|| first_to_finish(false)).unwrap_or(0);
+        loads[idx].running += 1;
+        loads[idx].pending_steps += steps;
+        WorkerLease {
+            pool: self,
+            idx,
+            steps,
+            started: Instant::now(),
+        }
+    }
+
+    /// Persist the measured speeds in `out_dir` for the scheduling of future builds
+    pub fn save(&self, out_dir: &Path) -> std::io::Result<()> {
+        let loads = self.loads.lock().unwrap();
+        let names = std::iter::once(LOCAL).chain(self.remotes.iter().map(|v| v.host.as_str()));
+        let speeds: BTreeMap<&str, f64> = names
+            .zip(loads.iter())
+            .filter_map(|(name, load)| Some((name, load.secs_per_step?)))
+            .collect();
+        let file = File::create(out_dir.join(SPEED_FILE_NAME))?;
+        serde_json::to_writer_pretty(file, &speeds).map_err(std::io::Error::from)
+    }
+}
+
+impl WorkerLease<'_> {
+    /// The remote worker to export on, or None to export locally
+    pub fn remote(&self) -> Option<&RemoteWorker> {
+        self.idx.checked_sub(1).map(|v| &self.pool.remotes[v])
+    }
+
+    /// Name of the worker for log messages
+    pub fn name(&self) -> &str {
+        self.remote().map_or(LOCAL, |v| v.host.as_str())
+    }
+
+    /// Update the speed estimate of the worker after its export succeeded
+    pub fn succeeded(&self) {
+        let secs_per_step = self.started.elapsed().as_secs_f64() / self.steps.max(1) as f64;
+        let mut loads = self.pool.loads.lock().unwrap();
+        let load = &mut loads[self.idx];
+        load.secs_per_step = Some(match load.secs_per_step {
+            Some(old) => (1.0 - SPEED_SMOOTHING) * old + SPEED_SMOOTHING * secs_per_step,
+            None => secs_per_step,
+        });
+    }
+}
+
+impl Drop for WorkerLease<'_> {
+    fn drop(&mut self) {
+        let mut loads = self.pool.loads.lock().unwrap();
+        loads[self.idx].running -= 1;
+        loads[self.idx].pending_steps -= self.steps;
+    }
+}
+
+/// Quote `arg` for the remote shell, which ssh passes the command to
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Export the staged copies in `staging_dir` with `worker` and copy the images to the output folder.
+/// Returns the output and command of the remote drawio process, like a local export
+pub fn export(
+    ctx: &BuildContext,
+    worker: &RemoteWorker,
+    file: &Path,
+    config: &BuildConfig,
+    staging_dir: &Path,
+) -> Result<(Output, DrawioCommand), DrawioError> {
+    let out_dir = Path::new(ctx.out_dir);
+    let run = |program: &str, args: &[&OsStr]| {
+        //never wait for a password prompt that nobody answers
+        let mut all_args = vec![OsStr::new("-o"), OsStr::new("BatchMode=yes")];
+        all_args.extend(args);
+        let process = spawn_process(ctx, OsStr::new(program), all_args, &BTreeMap::new(), file, out_dir)?;
+        wait_drawio(ctx, process, file, out_dir)
+    };
+    let host = OsStr::new(&worker.host);
+
+    let (output, _) = run("ssh", &[host, OsStr::new("mktemp -d -t drawio-builder.XXXXXX")])?;
+    let remote_dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    let remote_in = remote_dir.join("in");
+    let remote_out = remote_dir.join("out");
+    let result = (|| {
+        let destination = format!("{}:{}", worker.host, remote_in.display());
+        run("scp", &[OsStr::new("-q"), OsStr::new("-r"), staging_dir.as_os_str(), OsStr::new(&destination)])?;
+
+        let mut command = vec![
+            "mkdir".to_string(),
+            "-p".to_string(),
+            shell_quote(&remote_out.display().to_string()),
+            "&&".to_string(),
+            "env".to_string(),
+        ];
+        command.extend(config.env.iter().map(|(k, v)| shell_quote(&format!("{}={}", k, v))));
+        command.push(shell_quote(&worker.drawio));
+        command.extend(config.flags.iter().chain(ctx.electron_args).map(|v| shell_quote(v)));
+        command.extend(["-o", &remote_out.display().to_string(), &remote_in.display().to_string()].map(shell_quote));
+        let exported = run("ssh", &[host, OsStr::new(&command.join(" "))])?;
+
+        //the remote shell expands the glob
+        let source = format!("{}:{}/*", worker.host, remote_out.display());
+        run("scp", &[OsStr::new("-q"), OsStr::new(&source), out_dir.as_os_str()])?;
+        Ok(exported)
+    })();
+    let cleanup = format!("rm -rf {}", shell_quote(&remote_dir.display().to_string()));
+    //a leftover temp folder on the worker is no reason to fail the build
+    let _ = run("ssh", &[host, OsStr::new(&cleanup)]);
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn worker(host: &str, jobs: usize) -> RemoteWorker {
+        RemoteWorker {
+            host: host.to_string(),
+            drawio: default_drawio(),
+            jobs,
+        }
+    }
+
+    #[test]
+    fn test_acquire_balances_by_speed() {
+        let history = BTreeMap::from([(LOCAL.to_string(), 4.0), ("fast".to_string(), 1.0)]);
+        let pool = WorkerPool::new(vec![worker("fast", 1), worker("new", 2)], 2, &history);
+        //workers without history are tried first, until they have no free slot
+        let leases: Vec<_> = (0..4).map(|_| pool.acquire(3)).collect();
+        let names: Vec<_> = leases.iter().map(|v| v.name()).collect();
+        assert_eq!(names, ["new", "new", "fast", "local"]);
+        assert_eq!(pool.total_jobs(), 5);
+        assert_eq!(pool.acquire(3).name(), "local");
+        drop(leases);
+        assert_eq!(pool.acquire(3).name(), "new");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+
+        let mut pool = WorkerPool::new(vec![worker("fast", 2)], 2, &history);
+        pool.disable_local();
+        assert_eq!(pool.total_jobs(), 2);
+        let leases: Vec<_> = (0..2).map(|_| pool.acquire(3)).collect();
+        assert!(leases.iter().all(|v| v.name() == "fast"));
+    }
+}
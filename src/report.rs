@@ -0,0 +1,343 @@
+//! Per export step results of a build, e.g. for CI test reports
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use xmltree::{Element, EmitterConfig, XMLNode};
+
+use crate::{layer_steps, output_path, BuildConfig, DrawioCommand, DrawioError};
+
+pub enum StepStatus {
+    Built,
+    UpToDate,
+    Failed(StepFailure),
+    ///Not attempted, because the build was aborted after another step failed
+    NotRun,
+}
+
+#[derive(Serialize)]
+pub struct StepFailure {
+    pub message: String,
+    ///None if the step failed before drawio was started
+    pub command: Option<DrawioCommand>,
+    ///None if drawio did not terminate normally, e.g. because it was killed, or was never started
+    pub exit_code: Option<i32>,
+    ///Signal that killed drawio, e.g. 9 if it was killed by the OOM killer. Always None on Windows
+    pub signal: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    ///Crash dumps and logs left behind by electron, if drawio crashed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub crash_artifacts: Vec<PathBuf>,
+}
+
+pub struct StepResult {
+    pub file: PathBuf,
+    pub output_path: PathBuf,
+    pub status: StepStatus,
+}
+
+#[derive(Default)]
+pub struct BuildReport {
+    results: Mutex<Vec<StepResult>>,
+}
+
+impl BuildReport {
+    pub fn record(&self, file: &Path, output_path: &Path, status: StepStatus) {
+        self.results.lock().unwrap().push(StepResult {
+            file: file.to_path_buf(),
+            output_path: output_path.to_path_buf(),
+            status,
+        });
+    }
+
+    /// Mark all of `output_paths` without a result as failed with `error`
+    pub fn record_failure(&self, file: &Path, output_paths: &[&Path], error: &DrawioError) {
+        let mut results = self.results.lock().unwrap();
+        for output_path in output_paths {
+            if results.iter().any(|r| r.output_path == *output_path) {
+                continue;
+            }
+            results.push(StepResult {
+                file: file.to_path_buf(),
+                output_path: output_path.to_path_buf(),
+                status: StepStatus::Failed(StepFailure {
+                    message: error.message.clone(),
+                    command: error.command.clone(),
+                    exit_code: error.exit_code.and_then(|v| v.code()),
+                    signal: error.exit_code.and_then(exit_signal),
+                    stdout: String::from_utf8_lossy(&error.stdout).into_owned(),
+                    stderr: String::from_utf8_lossy(&error.stderr).into_owned(),
+                    crash_artifacts: error.crash_artifacts.clone(),
+                }),
+            });
+        }
+    }
+
+    /// Results for all steps of `planned`, sorted by output path. Steps without a result are reported as not run
+    pub fn finish(self, planned: &[(PathBuf, BuildConfig)], out_dir: &str) -> Vec<StepResult> {
+        let mut results = self.results.into_inner().unwrap();
+        for (file, config) in planned {
+            for idx in 0..layer_steps(&config.layer_config).len() {
+                let output_path = output_path(file, config, out_dir, idx);
+                if !results.iter().any(|r| r.output_path == output_path) {
+                    results.push(StepResult {
+                        file: file.clone(),
+                        output_path,
+                        status: StepStatus::NotRun,
+                    });
+                }
+            }
+        }
+        results.sort_by(|a, b| a.output_path.cmp(&b.output_path));
+        results
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+fn element(name: &str, attributes: &[(&str, String)]) -> Element {
+    let mut element = Element::new(name);
+    for (key, value) in attributes {
+        element.attributes.insert(key.to_string(), xml_text(value));
+    }
+    element
+}
+
+/// `text` with ANSI escape sequences removed and other characters XML 1.0 does not allow replaced.
+/// drawio's output may contain both, and a single invalid character makes CI systems reject the whole report
+fn xml_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            //control sequences like colors: ESC [ parameters final byte
+            '\u{1b}' if chars.peek() == Some(&'[') => {
+                chars.next();
+                while chars.next().is_some_and(|v| !('\u{40}'..='\u{7e}').contains(&v)) {}
+            }
+            '\t' | '\n' | '\r' => result.push(c),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => result.push(char::REPLACEMENT_CHARACTER),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Write `results` as JUnit XML. Each input file is a test suite and each export step a test case
+pub fn write_junit(results: &[StepResult], path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut by_file: BTreeMap<&Path, Vec<&StepResult>> = BTreeMap::new();
+    for result in results {
+        by_file.entry(&result.file).or_default().push(result);
+    }
+
+    let count = |results: &[&StepResult], f: fn(&StepStatus) -> bool| {
+        results.iter().filter(|r| f(&r.status)).count().to_string()
+    };
+    let is_failure = |s: &StepStatus| matches!(s, StepStatus::Failed(_));
+    let is_skipped = |s: &StepStatus| matches!(s, StepStatus::UpToDate | StepStatus::NotRun);
+
+    let all: Vec<&StepResult> = results.iter().collect();
+    let mut root = element(
+        "testsuites",
+        &[
+            ("name", "drawio-builder".to_string()),
+            ("tests", all.len().to_string()),
+            ("failures", count(&all, is_failure)),
+            ("skipped", count(&all, is_skipped)),
+        ],
+    );
+    for (file, results) in by_file {
+        let file = file.display().to_string();
+        let mut suite = element(
+            "testsuite",
+            &[
+                ("name", file.clone()),
+                ("tests", results.len().to_string()),
+                ("failures", count(&results, is_failure)),
+                ("skipped", count(&results, is_skipped)),
+            ],
+        );
+        for result in results {
+            let mut case = element(
+                "testcase",
+                &[
+                    ("classname", file.clone()),
+                    ("name", result.output_path.display().to_string()),
+                ],
+            );
+            match &result.status {
+                StepStatus::Built => (),
+                StepStatus::UpToDate => {
+                    let skipped = element("skipped", &[("message", "up to date".to_string())]);
+                    case.children.push(XMLNode::Element(skipped));
+                }
+                StepStatus::NotRun => {
+                    let skipped = element(
+                        "skipped",
+                        &[("message", "not built because of an earlier failure".to_string())],
+                    );
+                    case.children.push(XMLNode::Element(skipped));
+                }
+                StepStatus::Failed(failure_details) => {
+                    let mut failure = element("failure", &[("message", failure_details.message.clone())]);
+                    let output = format!("{}{}", failure_details.stdout, failure_details.stderr);
+                    failure.children.push(XMLNode::Text(xml_text(&output)));
+                    case.children.push(XMLNode::Element(failure));
+                }
+            }
+            suite.children.push(XMLNode::Element(case));
+        }
+        root.children.push(XMLNode::Element(suite));
+    }
+
+    let file = File::create(path)?;
+    root.write_with_config(file, EmitterConfig::new().perform_indent(true))?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ErrorLogEntry<'a> {
+    input: &'a Path,
+    output: &'a Path,
+    #[serde(flatten)]
+    failure: &'a StepFailure,
+}
+
+/// Write all failed steps of `results` as a JSON array, e.g. to triage failures programmatically.
+/// Each entry contains the exact drawio command, so that the failure can be reproduced
+pub fn write_error_log(results: &[StepResult], path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let entries: Vec<ErrorLogEntry> = results
+        .iter()
+        .filter_map(|result| match &result.status {
+            StepStatus::Failed(failure) => Some(ErrorLogEntry {
+                input: &result.file,
+                output: &result.output_path,
+                failure,
+            }),
+            _ => None,
+        })
+        .collect();
+    serde_json::to_writer_pretty(File::create(path)?, &entries)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_junit() {
+        let result = |output: &str, status| StepResult {
+            file: PathBuf::from("fig.drawio"),
+            output_path: PathBuf::from(output),
+            status,
+        };
+        let results = vec![
+            result("fig-0.png", StepStatus::Built),
+            result("fig-1.png", StepStatus::UpToDate),
+            result(
+                "fig-2.png",
+                StepStatus::Failed(StepFailure {
+                    message: "error exit code".to_string(),
+                    command: None,
+                    exit_code: Some(1),
+                    signal: None,
+                    stdout: String::new(),
+                    stderr: "\u{1b}[31m<crash>\u{1b}[0m\u{7}".to_string(),
+                    crash_artifacts: Vec::new(),
+                }),
+            ),
+        ];
+        let path = std::env::temp_dir().join(format!("drawio-builder-junit-{}.xml", std::process::id()));
+        write_junit(&results, &path).unwrap();
+        let xml = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let root = Element::parse(xml.as_bytes()).unwrap();
+        assert_eq!(root.attributes["tests"], "3");
+        assert_eq!(root.attributes["failures"], "1");
+        assert_eq!(root.attributes["skipped"], "1");
+        let suite = root.get_child("testsuite").unwrap();
+        let failed = suite.children.iter().filter_map(|v| v.as_element()).nth(2).unwrap();
+        let failure = failed.get_child("failure").unwrap();
+        assert_eq!(failure.attributes["message"], "error exit code");
+        assert_eq!(failure.get_text().unwrap(), "<crash>\u{fffd}");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_record_failure_signal() {
+        use std::os::unix::process::ExitStatusExt;
+        let report = BuildReport::default();
+        let error = DrawioError {
+            message: "error exit code".to_string(),
+            input_path: PathBuf::from("fig.drawio"),
+            output_path: PathBuf::from("fig-0.png"),
+            stderr: Vec::new(),
+            stdout: Vec::new(),
+            exit_code: Some(ExitStatus::from_raw(9)),
+            command: None,
+            crash_artifacts: Vec::new(),
+        };
+        report.record_failure(Path::new("fig.drawio"), &[Path::new("fig-0.png")], &error);
+        let results = report.results.into_inner().unwrap();
+        let StepStatus::Failed(failure) = &results[0].status else {
+            panic!("step did not fail");
+        };
+        assert_eq!((failure.exit_code, failure.signal), (None, Some(9)));
+    }
+
+    #[test]
+    fn test_write_error_log() {
+        let results = vec![
+            StepResult {
+                file: PathBuf::from("fig.drawio"),
+                output_path: PathBuf::from("fig-0.png"),
+                status: StepStatus::Built,
+            },
+            StepResult {
+                file: PathBuf::from("fig.drawio"),
+                output_path: PathBuf::from("fig-1.png"),
+                status: StepStatus::Failed(StepFailure {
+                    message: "error exit code".to_string(),
+                    command: Some(DrawioCommand {
+                        argv: vec!["drawio".to_string(), "-x".to_string()],
+                        working_dir: PathBuf::from("/work"),
+                        env: BTreeMap::from([("DISPLAY".to_string(), ":0".to_string())]),
+                    }),
+                    exit_code: Some(1),
+                    signal: None,
+                    stdout: String::new(),
+                    stderr: "<crash>".to_string(),
+                    crash_artifacts: vec![PathBuf::from("/home/user/.config/draw.io/Crashpad/completed/1.dmp")],
+                }),
+            },
+        ];
+        let path = std::env::temp_dir().join(format!("drawio-builder-errors-{}.json", std::process::id()));
+        write_error_log(&results, &path).unwrap();
+        let json: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let entries = json.as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["output"], "fig-1.png");
+        assert_eq!(entries[0]["command"]["argv"][0], "drawio");
+        assert_eq!(entries[0]["command"]["env"]["DISPLAY"], ":0");
+        assert_eq!(entries[0]["exit_code"], 1);
+        assert!(entries[0]["signal"].is_null());
+        assert_eq!(entries[0]["crash_artifacts"][0], "/home/user/.config/draw.io/Crashpad/completed/1.dmp");
+    }
+}
@@ -0,0 +1,45 @@
+//! Optional log of everything a build does, e.g. to find out after the fact why a figure was not rebuilt
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub struct RunLog {
+    ///None if logging is disabled
+    file: Option<Mutex<File>>,
+    start: Instant,
+}
+
+impl RunLog {
+    /// Log to a new file at `path`. If `path` is None, all messages are discarded
+    pub fn create(path: Option<&Path>) -> std::io::Result<Self> {
+        let file = match path {
+            Some(path) => Some(Mutex::new(File::create(path)?)),
+            None => None,
+        };
+        Ok(RunLog {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    /// Append `message`, prefixed with the time since the start of the build.
+    /// Lines are written immediately, so that the log is complete even if the build is aborted
+    pub fn log(&self, message: impl AsRef<str>) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let elapsed = self.start.elapsed().as_secs_f64();
+        //losing a log line is no reason to fail the build
+        let _ = writeln!(file.lock().unwrap(), "[{:9.3}s] {}", elapsed, message.as_ref());
+    }
+
+    /// Log the captured output of a drawio process, if there is any
+    pub fn log_output(&self, name: &str, output: &[u8]) {
+        if self.file.is_some() && !output.trim_ascii().is_empty() {
+            self.log(format!("{}:\n{}", name, String::from_utf8_lossy(output).trim_end()));
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! `split` subcommand: write each page of a multi-page .drawio file to its own file
+
+use clap::Args;
+use snafu::prelude::*;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+use crate::diagram::DrawioDocument;
+use crate::{sanitize_file_name, AppError};
+
+#[derive(Args)]
+pub struct SplitArgs {
+    ///Multi-page .drawio file
+    input: PathBuf,
+
+    ///Folder for the per-page files. Will be created if it does not exist
+    #[arg(long)]
+    out: PathBuf,
+}
+
+pub fn run(args: &SplitArgs) -> Result<(), AppError> {
+    let document = DrawioDocument::load(&args.input)
+        .whatever_context::<String, AppError>(format!("failed to parse file {:?}", &args.input))?;
+    create_dir_all(&args.out).whatever_context::<String, AppError>(format!("Failed to create output dir at {:?}", &args.out))?;
+
+    let mut used_names = Vec::new();
+    for (idx, page) in document.pages().iter().enumerate() {
+        let mut name = sanitize_file_name(page.name());
+        if name.is_empty() {
+            name = format!("page-{}", idx + 1);
+        }
+        //page names need not be unique, but file names do
+        if used_names.contains(&name) {
+            name = (2..)
+                .map(|n| format!("{}-{}", name, n))
+                .find(|v| !used_names.contains(v))
+                .unwrap();
+        }
+
+        let mut single = DrawioDocument::new();
+        single.push_page(page, page.name());
+        let target = args.out.join(format!("{}.drawio", name));
+        single
+            .save(&target)
+            .whatever_context::<String, AppError>(format!("failed to write {:?}", &target))?;
+        println!("{} -> {}", page.name(), target.display());
+        used_names.push(name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_file_names() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-split-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let names = ["Overview", "a/b: c?", "Overview", "Overview", "  ", "Overview-2"];
+        let pages: String = names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| {
+                format!(
+                    r#"<diagram id="{}" name="{}"><mxGraphModel><root><mxCell id="0"/></root></mxGraphModel></diagram>"#,
+                    idx, name
+                )
+            })
+            .collect();
+        let input = dir.join("deck.drawio");
+        fs::write(&input, format!("<mxfile>{}</mxfile>", pages)).unwrap();
+        let args = SplitArgs {
+            input,
+            out: dir.join("pages"),
+        };
+        run(&args).unwrap();
+
+        let mut files: Vec<String> = fs::read_dir(&args.out)
+            .unwrap()
+            .map(|v| v.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "Overview-2-2.drawio",
+                "Overview-2.drawio",
+                "Overview-3.drawio",
+                "Overview.drawio",
+                "a_b_ c_.drawio",
+                "page-5.drawio"
+            ]
+        );
+        //the page keeps its original name
+        let page = DrawioDocument::load(&args.out.join("a_b_ c_.drawio")).unwrap();
+        assert_eq!(page.pages()[0].name(), "a/b: c?");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
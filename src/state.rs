@@ -0,0 +1,235 @@
+//! Build state persisted in the output folder between runs.
+//!
+//! Timestamps only tell us whether a figure changed, not whether the settings used to export it changed.
+//! Thus, we additionally remember a fingerprint of the build settings for each input file.
+//! Timestamps in the future, e.g. of files restored from archives or synced from machines with a skewed clock,
+//! would make outputs look up to date forever. For these files, we compare a hash of their content instead
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::mock::Backend;
+use crate::{layer_steps, BuildConfig};
+
+const STATE_FILE_NAME: &str = "drawio-builder-state.json";
+
+#[derive(Default, Serialize, Deserialize)]
+struct StateFile {
+    ///Maps input file names to the fingerprint of the settings they were last built with
+    fingerprints: BTreeMap<String, String>,
+    ///Maps input file names to the hash of their content when they were last built
+    #[serde(default)]
+    input_hashes: BTreeMap<String, String>,
+}
+
+pub struct BuildState {
+    path: PathBuf,
+    previous: StateFile,
+    ///Fingerprints and input hashes of files that have been successfully built in this run
+    updated: Mutex<HashMap<String, (String, String)>>,
+}
+
+/// How to decide whether the existing outputs of a file are up to date
+pub enum Freshness {
+    ///Outputs that are newer than the input are up to date
+    Timestamp,
+    ///All existing outputs are up to date, because the content of the input did not change
+    Exists,
+    ///All outputs are outdated, for the given reason
+    Rebuild(&'static str),
+}
+
+impl BuildState {
+    /// Load the state from `out_dir`. A missing or unreadable state file results in an empty state
+    pub fn load(out_dir: &Path) -> Self {
+        let path = out_dir.join(STATE_FILE_NAME);
+        let previous = File::open(&path)
+            .ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+            .unwrap_or_default();
+        BuildState {
+            path,
+            previous,
+            updated: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true if `file_name` has previously been built with different settings.
+    /// Files without a recorded fingerprint are not considered changed, so that we only fall back to the timestamps
+    fn settings_changed(&self, file_name: &str, fingerprint: &str) -> bool {
+        self.previous
+            .fingerprints
+            .get(file_name)
+            .is_some_and(|v| v != fingerprint)
+    }
+
+    /// Decide how to check the outputs of a file, stored under `file_name`, for freshness.
+    /// `sources` are the file and its dependencies, `input_hash` is their `hash_files`
+    pub fn freshness(&self, file_name: &str, fingerprint: &str, sources: &[PathBuf], input_hash: &str) -> Freshness {
+        if self.settings_changed(file_name, fingerprint) {
+            return Freshness::Rebuild("build settings changed");
+        }
+        if !sources.iter().any(|v| modified_in_future(v)) {
+            return Freshness::Timestamp;
+        }
+        match self.previous.input_hashes.get(file_name) {
+            Some(hash) if hash == input_hash => Freshness::Exists,
+            Some(_) => Freshness::Rebuild("source content changed"),
+            None => Freshness::Rebuild("no content hash recorded for source"),
+        }
+    }
+
+    pub fn record(&self, file_name: &str, fingerprint: String, input_hash: String) {
+        self.updated
+            .lock()
+            .unwrap()
+            .insert(file_name.to_string(), (fingerprint, input_hash));
+    }
+
+    pub fn save(self) -> std::io::Result<()> {
+        let mut state = self.previous;
+        for (file_name, (fingerprint, input_hash)) in self.updated.into_inner().unwrap() {
+            state.fingerprints.insert(file_name.clone(), fingerprint);
+            state.input_hashes.insert(file_name, input_hash);
+        }
+        let file = File::create(&self.path)?;
+        serde_json::to_writer_pretty(file, &state)?;
+        Ok(())
+    }
+}
+
+/// True if the modification time of `file` lies in the future, i.e. cannot be compared to those of the outputs
+pub fn modified_in_future(file: &Path) -> bool {
+    file.metadata()
+        .and_then(|v| v.modified())
+        .is_ok_and(|v| v > SystemTime::now())
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Add `value` to `hasher`, prefixed with `tag` and its length. Otherwise, different sections and values,
+/// e.g. the env variable "A=B" with the value "C" and "A" with "B=C", could hash to the same byte stream
+fn update_tagged(hasher: &mut Sha256, tag: &str, value: &[u8]) {
+    hasher.update(tag.as_bytes());
+    hasher.update([0]);
+    hasher.update((value.len() as u64).to_le_bytes());
+    hasher.update(value);
+}
+
+/// Hash of the content of `files`, e.g. a figure and its dependencies
+pub fn hash_files(files: &[PathBuf]) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    for file in files {
+        update_tagged(&mut hasher, "file", &fs::read(file)?);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Hash of all settings that influence the exported images of a file, when exported with `backend`
+pub fn fingerprint(config: &BuildConfig, backend: Backend) -> String {
+    let mut hasher = Sha256::new();
+    //placeholders must not pass as up to date in a drawio build and vice versa
+    if backend == Backend::Mock {
+        update_tagged(&mut hasher, "backend", b"mock");
+    }
+    for flag in &config.flags {
+        update_tagged(&mut hasher, "flag", flag.as_bytes());
+    }
+    for (key, value) in &config.env {
+        update_tagged(&mut hasher, "env-key", key.as_bytes());
+        update_tagged(&mut hasher, "env-value", value.as_bytes());
+    }
+    for (name, value) in &config.variables {
+        update_tagged(&mut hasher, "variable-name", name.as_bytes());
+        update_tagged(&mut hasher, "variable-value", value.as_bytes());
+    }
+    if let Some((locale, translations)) = &config.locale {
+        update_tagged(&mut hasher, "locale", locale.as_bytes());
+        for (text, translation) in translations {
+            update_tagged(&mut hasher, "text", text.as_bytes());
+            update_tagged(&mut hasher, "translation", translation.as_bytes());
+        }
+    }
+    for step in layer_steps(&config.layer_config) {
+        update_tagged(&mut hasher, "layer-step", format!("{:?}", step).as_bytes());
+    }
+    hex(&hasher.finalize())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LayerConfig;
+
+    #[test]
+    fn test_fingerprint_env() {
+        let mut config = BuildConfig {
+            flags: vec!["-x".to_string()],
+            env: BTreeMap::new(),
+            layer_config: LayerConfig::Incremental(2),
+            variables: BTreeMap::new(),
+            locale: None,
+            page: None,
+            max_output_kb: None,
+        };
+        let without_env = fingerprint(&config, Backend::Drawio);
+        assert_ne!(without_env, fingerprint(&config, Backend::Mock));
+        config
+            .env
+            .insert("ELECTRON_DISABLE_GPU".to_string(), "1".to_string());
+        assert_ne!(without_env, fingerprint(&config, Backend::Drawio));
+    }
+
+    #[test]
+    fn test_fingerprint_sections() {
+        let config = |flags: &[&str], env: &[(&str, &str)]| BuildConfig {
+            flags: flags.iter().map(|v| v.to_string()).collect(),
+            env: env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            layer_config: LayerConfig::Incremental(1),
+            variables: BTreeMap::new(),
+            locale: None,
+            page: None,
+            max_output_kb: None,
+        };
+        let fingerprints = [
+            fingerprint(&config(&[], &[("A=B", "C")]), Backend::Drawio),
+            fingerprint(&config(&[], &[("A", "B=C")]), Backend::Drawio),
+            fingerprint(&config(&["-a\0-b"], &[]), Backend::Drawio),
+            fingerprint(&config(&["-a", "-b"], &[]), Backend::Drawio),
+            fingerprint(&config(&["-a"], &[]), Backend::Drawio),
+        ];
+        let unique: std::collections::HashSet<_> = fingerprints.iter().collect();
+        assert_eq!(unique.len(), fingerprints.len());
+    }
+
+    #[test]
+    fn test_freshness_future_mtime() {
+        let dir = std::env::temp_dir().join(format!("drawio-builder-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("fig.drawio");
+        std::fs::write(&file, "<mxfile/>").unwrap();
+        let sources = [file.clone()];
+        let hash = hash_files(&sources).unwrap();
+
+        let state = BuildState::load(&dir);
+        assert!(matches!(state.freshness("fig.drawio", "f", &sources, &hash), Freshness::Timestamp));
+        state.record("fig.drawio", "f".to_string(), hash.clone());
+        state.save().unwrap();
+
+        let tomorrow = SystemTime::now() + std::time::Duration::from_secs(24 * 60 * 60);
+        File::options().write(true).open(&file).unwrap().set_modified(tomorrow).unwrap();
+        let state = BuildState::load(&dir);
+        assert!(matches!(state.freshness("fig.drawio", "f", &sources, &hash), Freshness::Exists));
+        assert!(matches!(state.freshness("fig.drawio", "f", &sources, "other"), Freshness::Rebuild(_)));
+        assert!(matches!(state.freshness("fig.drawio", "g", &sources, &hash), Freshness::Rebuild(_)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! `stats` subcommand: summarize the size of a project, e.g. to keep track of figure sprawl in big decks
+
+use clap::Args;
+use serde::Serialize;
+use snafu::prelude::*;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::diagram::DrawioDocument;
+use crate::{input_files, layer_steps, load_config, load_translations, output_path, plan_build, AppError, DEFAULT_BUILD_ARGS};
+
+#[derive(Args)]
+pub struct StatsArgs {
+    ///Path to folder with input files
+    #[arg(short, long, default_value = "./")]
+    input: String,
+
+    ///Path to folder with the exported figures
+    #[arg(short, long, default_value = "./out")]
+    output: String,
+
+    ///Path to optional config file
+    #[arg(long)]
+    config: Option<String>,
+
+    ///Drawio build args, as passed to the build. Required to determine the exported page
+    #[arg(long, default_value = DEFAULT_BUILD_ARGS)]
+    build_args: String,
+
+    ///Set if the build exports all pages
+    #[arg(long, default_value = "false")]
+    all_pages: bool,
+
+    ///Translation folder, if the build exports localized figures
+    #[arg(long)]
+    locales: Option<String>,
+
+    ///Set if the build searches sub folders of the input folder
+    #[arg(long, default_value = "false")]
+    recursive: bool,
+
+    ///Set if the build skips input files excluded by .gitignore or .ignore files
+    #[arg(long, default_value = "false")]
+    respect_ignore_files: bool,
+
+    ///Print JSON instead of a table
+    #[arg(long, default_value = "false")]
+    json: bool,
+}
+
+#[derive(Serialize, Default)]
+struct Counts {
+    pages: usize,
+    layers: usize,
+    shapes: usize,
+    text_labels: usize,
+    export_steps: usize,
+    ///Size of the exported figures in bytes. Figures that have not been built yet are not counted
+    output_bytes: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.pages += other.pages;
+        self.layers += other.layers;
+        self.shapes += other.shapes;
+        self.text_labels += other.text_labels;
+        self.export_steps += other.export_steps;
+        self.output_bytes += other.output_bytes;
+    }
+}
+
+#[derive(Serialize)]
+struct FileStats {
+    file: PathBuf,
+    #[serde(flatten)]
+    counts: Counts,
+}
+
+#[derive(Serialize)]
+struct ProjectStats {
+    files: Vec<FileStats>,
+    total: Counts,
+}
+
+/// Pages, layers, shapes and text labels of `document`
+fn document_counts(document: &DrawioDocument) -> Counts {
+    let mut counts = Counts::default();
+    for page in document.pages() {
+        counts.pages += 1;
+        counts.layers += page.layers().len();
+        //layers are not shapes and their value is their name
+        let content = page.cells().iter().filter(|c| page.layer_index(c).is_some());
+        counts.shapes += content.clone().filter(|c| c.is_vertex()).count();
+        counts.text_labels += content.filter(|c| !c.text().is_empty()).count();
+    }
+    counts
+}
+
+fn collect(args: &StatsArgs) -> Result<ProjectStats, AppError> {
+    let config = load_config(args.config.as_deref())?;
+    let drawio_flags: Vec<String> = args.build_args.split(' ').map(|v| v.to_string()).collect();
+    let translations = load_translations(args.locales.as_deref())?;
+    let files = input_files(&args.input, args.recursive, args.respect_ignore_files)?;
+    let planned = plan_build(files, &config, &drawio_flags, args.all_pages, &translations, &mut |v| {
+        eprintln!("Warning: {}", v)
+    })?;
+
+    //with "--all-pages" or "--locales", a file is built with several configs
+    let mut files: BTreeMap<PathBuf, Counts> = BTreeMap::new();
+    for (file, build_config) in planned {
+        if !files.contains_key(&file) {
+            let document = DrawioDocument::load(&file)
+                .whatever_context::<String, AppError>(format!("failed to parse file {:?}", &file))?;
+            files.insert(file.clone(), document_counts(&document));
+        }
+        let counts = files.get_mut(&file).unwrap();
+        let steps = layer_steps(&build_config.layer_config).len();
+        counts.export_steps += steps;
+        counts.output_bytes += (0..steps)
+            .filter_map(|idx| output_path(&file, &build_config, &args.output, idx).metadata().ok())
+            .map(|v| v.len())
+            .sum::<u64>();
+    }
+
+    let mut stats = ProjectStats {
+        files: Vec::new(),
+        total: Counts::default(),
+    };
+    for (file, counts) in files {
+        stats.total.add(&counts);
+        stats.files.push(FileStats { file, counts });
+    }
+    Ok(stats)
+}
+
+pub fn run(args: &StatsArgs) -> Result<(), AppError> {
+    let stats = collect(args)?;
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&stats)
+            .whatever_context::<&str, AppError>("failed to serialize stats")?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    let rows: Vec<(String, &Counts)> = stats
+        .files
+        .iter()
+        .map(|v| (v.file.display().to_string(), &v.counts))
+        .chain([(format!("Total ({} files)", stats.files.len()), &stats.total)])
+        .collect();
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or_default();
+    println!(
+        "{:width$}  {:>5}  {:>6}  {:>6}  {:>11}  {:>12}  {:>12}",
+        "File", "Pages", "Layers", "Shapes", "Text labels", "Export steps", "Output size"
+    );
+    for (name, counts) in rows {
+        println!(
+            "{:width$}  {:>5}  {:>6}  {:>6}  {:>11}  {:>12}  {:>12}",
+            name,
+            counts.pages,
+            counts.layers,
+            counts.shapes,
+            counts.text_labels,
+            counts.export_steps,
+            format_size(counts.output_bytes)
+        );
+    }
+    Ok(())
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1048576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+    use std::fs;
+    use std::path::Path;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: StatsArgs,
+    }
+
+    /// Pages, layers, shapes and text labels of `file`
+    fn counts(file: &str) -> (usize, usize, usize, usize) {
+        let counts = document_counts(&DrawioDocument::load(Path::new(file)).unwrap());
+        (counts.pages, counts.layers, counts.shapes, counts.text_labels)
+    }
+
+    #[test]
+    fn test_document_counts() {
+        //layers are neither counted as shapes nor as text labels
+        assert_eq!(counts("test-data/nonce-truncation.drawio"), (1, 5, 27, 19));
+        assert_eq!(counts("test-data/compressed.drawio"), (1, 1, 1, 1));
+    }
+
+    #[test]
+    fn test_collect() {
+        let out = std::env::temp_dir().join(format!("drawio-builder-stats-{}", std::process::id()));
+        fs::create_dir_all(&out).unwrap();
+        //only existing outputs count towards the output size
+        fs::write(out.join("nonce-truncation-0.png"), [0; 100]).unwrap();
+        fs::write(out.join("nonce-truncation-4.png"), [0; 20]).unwrap();
+        let input = "test-data/nonce-truncation.drawio";
+        let args = Cli::parse_from(["stats", "-i", input, "-o", out.to_str().unwrap()]).args;
+        let stats = collect(&args).unwrap();
+        assert_eq!(stats.files.len(), 1);
+        assert_eq!(stats.total.export_steps, 5);
+        assert_eq!(stats.total.output_bytes, 120);
+        assert_eq!(stats.total.layers, 5);
+        fs::remove_dir_all(&out).unwrap();
+    }
+}